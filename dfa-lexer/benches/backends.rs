@@ -0,0 +1,34 @@
+// Times the tree walker against the bytecode VM on call-heavy and
+// loop-heavy scripts. Run with `cargo bench`.
+use std::time::{Duration, Instant};
+
+use dfa_lexer::{Backend, Interpreter};
+
+const SCRIPTS: &[(&str, &str)] = &[
+    ("fib", "fn fib(n) = if n < 2 { n } else { fib(n - 1) + fib(n - 2) }\nfib(24)"),
+    ("loop", "fn sum(n) { let s = 0; let i = 0; while i < n { s = s + i * 2; i = i + 1 }; s }\nsum(300000)"),
+    ("blocks", "fn nest(n) { let t = 0; while n > 0 { { let a = n; { let b = a + 1; t = t + b - a } }; n = n - 1 }; t }\nnest(200000)"),
+];
+
+fn time(src: &str, backend: Backend) -> (Duration, String) {
+    let mut best = Duration::MAX;
+    let mut out = String::new();
+    for _ in 0..5 {
+        let mut interp = Interpreter::new().with_backend(backend);
+        let start = Instant::now();
+        out = interp.eval(src).expect("benchmark script runs").to_string();
+        best = best.min(start.elapsed());
+    }
+    (best, out)
+}
+
+fn main() {
+    println!("{:<8} {:>12} {:>12} {:>8}", "script", "tree", "vm", "speedup");
+    for (name, src) in SCRIPTS {
+        let (tree, a) = time(src, Backend::Tree);
+        let (vm, b) = time(src, Backend::Vm);
+        assert_eq!(a, b, "backends disagree on {}", name);
+        let speedup = tree.as_secs_f64() / vm.as_secs_f64();
+        println!("{:<8} {:>12.2?} {:>12.2?} {:>7.2}x", name, tree, vm, speedup);
+    }
+}
@@ -0,0 +1,309 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// Sign-magnitude integer with base 2^32 limbs, least significant first.
+// The magnitude never has trailing zero limbs and zero is never negative.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BigInt {
+    neg: bool,
+    mag: Vec<u32>,
+}
+
+// Results wider than this are refused rather than computed.
+pub const MAX_BITS: u64 = 1 << 22;
+
+fn trim(mag: &mut Vec<u32>) {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &x) in long.iter().enumerate() {
+        let sum = x as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        out.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        out.push(carry as u32);
+    }
+    out
+}
+
+// Requires |a| >= |b|.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &x) in a.iter().enumerate() {
+        let mut diff = x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        out.push(diff as u32);
+    }
+    trim(&mut out);
+    out
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut out = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let cur = out[i + j] as u64 + x as u64 * y as u64 + carry;
+            out[i + j] = cur as u32;
+            carry = cur >> 32;
+        }
+        out[i + b.len()] = carry as u32;
+    }
+    trim(&mut out);
+    out
+}
+
+fn divrem_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut out = vec![0u32; a.len()];
+    let mut rem = 0u64;
+    for i in (0..a.len()).rev() {
+        let cur = (rem << 32) | a[i] as u64;
+        out[i] = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+    trim(&mut out);
+    (out, rem as u32)
+}
+
+// Shift-subtract long division; fine for the operand sizes a calculator sees.
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if b.len() == 1 {
+        let (q, r) = divrem_small(a, b[0]);
+        return (q, if r == 0 { Vec::new() } else { vec![r] });
+    }
+
+    let mut quot = vec![0u32; a.len()];
+    let mut rem: Vec<u32> = Vec::with_capacity(b.len() + 1);
+    for bit in (0..a.len() * 32).rev() {
+        let mut carry = (a[bit / 32] >> (bit % 32)) & 1;
+        for limb in rem.iter_mut() {
+            let next = *limb >> 31;
+            *limb = (*limb << 1) | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            rem.push(carry);
+        }
+        if cmp_mag(&rem, b) != Ordering::Less {
+            rem = sub_mag(&rem, b);
+            quot[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    trim(&mut quot);
+    (quot, rem)
+}
+
+impl BigInt {
+    fn from_parts(neg: bool, mut mag: Vec<u32>) -> Self {
+        trim(&mut mag);
+        let neg = neg && !mag.is_empty();
+        Self { neg, mag }
+    }
+
+    pub fn from_i64(n: i64) -> Self {
+        let v = n.unsigned_abs();
+        Self::from_parts(n < 0, vec![v as u32, (v >> 32) as u32])
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let v = self.mag.iter().rev().fold(0u64, |acc, &l| (acc << 32) | l as u64);
+        if self.neg {
+            0i64.checked_sub_unsigned(v)
+        } else {
+            i64::try_from(v).ok()
+        }
+    }
+
+    pub fn parse_radix(digits: &str, radix: u32) -> Option<Self> {
+        if digits.is_empty() {
+            return None;
+        }
+        let mut mag: Vec<u32> = Vec::new();
+        for c in digits.chars() {
+            let d = c.to_digit(radix)?;
+            let mut carry = d as u64;
+            for limb in mag.iter_mut() {
+                let cur = *limb as u64 * radix as u64 + carry;
+                *limb = cur as u32;
+                carry = cur >> 32;
+            }
+            if carry > 0 {
+                mag.push(carry as u32);
+            }
+        }
+        Some(Self::from_parts(false, mag))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let v = self.mag.iter().rev().fold(0f64, |acc, &l| acc * 4294967296.0 + l as f64);
+        if self.neg { -v } else { v }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.neg
+    }
+
+    pub fn bits(&self) -> u64 {
+        match self.mag.last() {
+            None => 0,
+            Some(top) => (self.mag.len() as u64 - 1) * 32 + (32 - top.leading_zeros()) as u64,
+        }
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::from_parts(false, self.mag.clone())
+    }
+
+    // Truncating division, matching i64: the remainder takes the dividend's sign.
+    pub fn div_rem(&self, other: &Self) -> Option<(Self, Self)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (q, r) = divrem_mag(&self.mag, &other.mag);
+        Some((Self::from_parts(self.neg != other.neg, q), Self::from_parts(self.neg, r)))
+    }
+
+    pub fn pow(&self, mut exp: u64) -> Option<Self> {
+        // 0, 1 and -1 stay small whatever the exponent.
+        if exp == 0 {
+            return Some(Self::from_i64(1));
+        }
+        if self.bits() <= 1 {
+            return Some(Self::from_parts(self.neg && exp & 1 == 1, self.mag.clone()));
+        }
+        if self.bits().saturating_mul(exp) > MAX_BITS {
+            return None;
+        }
+        let mut base = self.clone();
+        let mut acc = Self::from_i64(1);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                acc = &acc * &base;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = &base * &base;
+            }
+        }
+        Some(acc)
+    }
+
+    pub fn gcd(&self, other: &Self) -> Self {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while let Some((_, r)) = a.div_rem(&b) {
+            a = b;
+            b = r;
+        }
+        a
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.neg == other.neg {
+            return BigInt::from_parts(self.neg, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::from_parts(other.neg, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::from_parts(self.neg, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(self.neg != other.neg, mul_mag(&self.mag, &other.mag))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.neg, self.mag.clone())
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        let mut chunks = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (q, r) = divrem_small(&mag, 1_000_000_000);
+            chunks.push(r);
+            mag = q;
+        }
+
+        if self.neg {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
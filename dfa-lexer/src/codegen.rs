@@ -0,0 +1,209 @@
+use std::fmt::Write;
+
+use crate::dfa::{DEAD, Dfa};
+use crate::spec::Rule;
+
+// Rule names become enum variants: IDENT -> Ident, RADIX_NUM -> RadixNum.
+fn variant(name: &str) -> String {
+    let mut out: String = name
+        .split('_')
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut cs = w.chars();
+            let first = cs.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(cs.map(|c| c.to_ascii_lowercase())).collect::<String>()
+        })
+        .collect();
+    if !out.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        out.insert(0, 'T');
+    }
+    // `Self` is the one keyword already in camel case; SELF must not produce it.
+    if out == "Self" {
+        out.push('_');
+    }
+    out
+}
+
+fn write_array<T>(out: &mut String, items: &[T], per_line: usize, item: impl Fn(&T) -> String) {
+    for chunk in items.chunks(per_line) {
+        let line: Vec<String> = chunk.iter().map(&item).collect();
+        writeln!(out, "    {},", line.join(", ")).unwrap();
+    }
+}
+
+// Emits a dependency-free Rust module that matches the same language as
+// `dfa`: the alphabet and transition table become static arrays and
+// `next_token` runs maximal munch over them. `dfa` should be built from
+// `rules`, ideally minimized.
+pub fn generate(rules: &[Rule], dfa: &Dfa, source: &str) -> String {
+    let mut names: Vec<String> = Vec::new();
+    let kinds: Vec<usize> = rules
+        .iter()
+        .map(|r| {
+            let v = variant(&r.name);
+            names.iter().position(|n| *n == v).unwrap_or_else(|| {
+                names.push(v);
+                names.len() - 1
+            })
+        })
+        .collect();
+
+    let width = dfa.classes();
+    let states = dfa.states();
+    let (state_ty, dead) = if states < u16::MAX as usize { ("u16", "u16::MAX") } else { ("u32", "u32::MAX") };
+    let class_ty = if width < u8::MAX as usize { "u8" } else { "u16" };
+
+    let mut out = String::new();
+    writeln!(out, "// Generated by `dfa-lexer gen` from {}. Do not edit.", source).unwrap();
+    writeln!(out, "// {} rules, {} states, {} character classes.", rules.len(), states, width).unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]").unwrap();
+    writeln!(out, "pub enum TokenKind {{").unwrap();
+    for name in &names {
+        writeln!(out, "    {},", name).unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "const CLASSES: usize = {};", width).unwrap();
+    writeln!(out, "const START: {} = {};", state_ty, dfa.start()).unwrap();
+    writeln!(out, "const DEAD: {} = {};\n", state_ty, dead).unwrap();
+
+    let ranges = dfa.alphabet().ranges();
+    writeln!(out, "// (first, last, class), sorted and disjoint.").unwrap();
+    writeln!(out, "static RANGES: [(char, char, {}); {}] = [", class_ty, ranges.len()).unwrap();
+    write_array(&mut out, ranges, 4, |&(lo, hi, class)| format!("({:?}, {:?}, {})", lo, hi, class));
+    writeln!(out, "];\n").unwrap();
+
+    writeln!(out, "// TABLE[state * CLASSES + class] is the next state.").unwrap();
+    writeln!(out, "static TABLE: [{}; {}] = [", state_ty, states * width).unwrap();
+    let table: Vec<usize> = (0..states * width).map(|i| dfa.transition(i / width, i % width)).collect();
+    write_array(&mut out, &table, 16, |&to| if to == DEAD { "DEAD".into() } else { to.to_string() });
+    writeln!(out, "];\n").unwrap();
+
+    writeln!(out, "static ACCEPT: [Option<TokenKind>; {}] = [", states).unwrap();
+    let accept: Vec<Option<usize>> = (0..states).map(|s| dfa.accepting(s)).collect();
+    write_array(&mut out, &accept, 4, |a| match a {
+        Some(rule) => format!("Some(TokenKind::{})", names[kinds[*rule]]),
+        None => "None".into(),
+    });
+    writeln!(out, "];\n").unwrap();
+
+    out.push_str(
+        r#"fn class_of(c: char) -> Option<usize> {
+    let i = RANGES.partition_point(|r| r.0 <= c);
+    if i == 0 {
+        return None;
+    }
+    let (lo, hi, class) = RANGES[i - 1];
+    (lo <= c && c <= hi).then_some(class as usize)
+}
+
+// The longest token at the start of `input` and its length in bytes, or
+// None if no rule matches a non-empty prefix.
+pub fn next_token(input: &str) -> Option<(TokenKind, usize)> {
+    let mut state = START;
+    let mut last = None;
+
+    for (i, c) in input.char_indices() {
+        let Some(class) = class_of(c) else { break };
+        state = TABLE[state as usize * CLASSES + class];
+        if state == DEAD {
+            break;
+        }
+        if let Some(kind) = ACCEPT[state as usize] {
+            last = Some((kind, i + c.len_utf8()));
+        }
+    }
+
+    last
+}
+"#,
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::TOKEN_SPEC;
+    use crate::nfa::Nfa;
+    use crate::spec;
+    use std::process::Command;
+
+    // Compiles the generated module with a small driver that prints every
+    // token it finds, and compares that with maximal munch on the runtime DFA.
+    fn agrees_with_runtime(spec_src: &str, name: &str, inputs: &[&str]) {
+        let rules = spec::parse(spec_src).unwrap();
+        let dfa = Dfa::from_nfa(&Nfa::from_rules(&rules)).minimize();
+        let mut src = generate(&rules, &dfa, name);
+        src.push_str(&format!(
+            r#"
+fn main() {{
+    for input in {:?} {{
+        let mut rest: &str = input;
+        while let Some(c) = rest.chars().next() {{
+            match next_token(rest) {{
+                Some((kind, len)) => {{
+                    println!("{{:?}} {{:?}}", kind, &rest[..len]);
+                    rest = &rest[len..];
+                }}
+                None => {{
+                    println!("- {{:?}}", c);
+                    rest = &rest[c.len_utf8()..];
+                }}
+            }}
+        }}
+    }}
+}}
+"#,
+            inputs
+        ));
+
+        let mut expected = String::new();
+        for input in inputs {
+            let chars: Vec<char> = input.chars().collect();
+            let mut at = 0;
+            while at < chars.len() {
+                match dfa.longest_match(&chars, at) {
+                    Some((end, rule)) => {
+                        let text: String = chars[at..end].iter().collect();
+                        writeln!(expected, "{} {:?}", variant(&rules[rule].name), text).unwrap();
+                        at = end;
+                    }
+                    None => {
+                        writeln!(expected, "- {:?}", chars[at]).unwrap();
+                        at += 1;
+                    }
+                }
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("dfa-lexer-gen-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lexer.rs"), src).unwrap();
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+        let status = Command::new(rustc)
+            .args(["--edition", "2024", "-o"])
+            .arg(dir.join("lexer"))
+            .arg(dir.join("lexer.rs"))
+            .status()
+            .unwrap();
+        assert!(status.success(), "generated code for {} does not compile", name);
+        let output = Command::new(dir.join("lexer")).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+
+    #[test]
+    fn builtin_spec_compiles_and_matches_the_runtime_dfa() {
+        agrees_with_runtime(TOKEN_SPEC, "builtin", &["let x = 0x1F + 3.5e-2 * y_2 # note\n", "fn f(a, b) { while a >= b && !done { a = a - 1 } }", "\"str\\\"ing\" /* c */ 名前 $ 1e"]);
+    }
+
+    #[test]
+    fn keyword_rule_names_are_escaped() {
+        let spec_src = "SELF = self\nSELF_TYPE = Self\nSTRUCT = struct\nMATCH = match\n_ = [ ]+\nIDENT = [a-zA-Z]+\n";
+        agrees_with_runtime(spec_src, "keywords", &["self Self struct match selfish x"]);
+        assert_eq!(variant("SELF"), "Self_");
+    }
+}
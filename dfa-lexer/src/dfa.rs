@@ -0,0 +1,309 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::nfa::Nfa;
+use crate::regex::{self, CharSet};
+
+pub const DEAD: usize = usize::MAX;
+
+// Characters are mapped to a small set of classes so the transition table
+// only needs one column per class instead of one per character.
+#[derive(Debug, Clone, Default)]
+pub struct Alphabet {
+    ranges: Vec<(char, char, usize)>,
+    classes: usize,
+}
+
+impl Alphabet {
+    // Splits the character space into the coarsest classes that no set in
+    // `sets` distinguishes, and reports which sets contain each class.
+    pub fn partition(sets: &[CharSet]) -> (Alphabet, Vec<Vec<bool>>) {
+        let mut bounds = BTreeSet::new();
+        for set in sets {
+            for &(lo, hi) in set {
+                bounds.insert(lo);
+                if let Some(n) = regex::next_char(hi) {
+                    bounds.insert(n);
+                }
+            }
+        }
+        let bounds: Vec<char> = bounds.into_iter().collect();
+
+        let mut alphabet = Alphabet::default();
+        let mut signatures: HashMap<Vec<bool>, usize> = HashMap::new();
+        let mut members: Vec<Vec<bool>> = Vec::new();
+
+        for (i, &lo) in bounds.iter().enumerate() {
+            let hi = match bounds.get(i + 1) {
+                Some(&next) => regex::prev_char(next).unwrap(),
+                None => char::MAX,
+            };
+            let signature: Vec<bool> = sets.iter().map(|s| regex::contains(s, lo)).collect();
+            if !signature.contains(&true) {
+                continue;
+            }
+
+            let class = *signatures.entry(signature.clone()).or_insert_with(|| {
+                members.push(signature);
+                members.len() - 1
+            });
+            alphabet.ranges.push((lo, hi, class));
+        }
+
+        alphabet.classes = members.len();
+        (alphabet, members)
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes == 0
+    }
+
+    // (first, last, class) triples, sorted and disjoint.
+    pub fn ranges(&self) -> &[(char, char, usize)] {
+        &self.ranges
+    }
+
+    pub fn class_of(&self, c: char) -> Option<usize> {
+        let i = self.ranges.partition_point(|r| r.0 <= c);
+        if i == 0 {
+            return None;
+        }
+        let (lo, hi, class) = self.ranges[i - 1];
+        (lo <= c && c <= hi).then_some(class)
+    }
+}
+
+// Accepting states carry the index of the spec rule they recognise.
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    alphabet: Alphabet,
+    table: Vec<usize>,
+    accept: Vec<Option<usize>>,
+    start: usize,
+}
+
+impl Dfa {
+    // Subset construction. Each DFA state is the epsilon closure of a set of
+    // NFA states; when several rules accept, the one declared first wins.
+    pub fn from_nfa(nfa: &Nfa) -> Self {
+        let (alphabet, members) = Alphabet::partition(&nfa.sets);
+
+        let start = nfa.closure(&[nfa.start]);
+        let mut ids: HashMap<Vec<usize>, usize> = HashMap::from([(start.clone(), 0)]);
+        let mut subsets = vec![start];
+        let mut table = Vec::new();
+        let mut accept = Vec::new();
+
+        let mut current = 0;
+        while current < subsets.len() {
+            let subset = subsets[current].clone();
+            accept.push(subset.iter().filter_map(|&s| nfa.accept[s]).min());
+
+            for member in &members {
+                let moved: Vec<usize> = subset
+                    .iter()
+                    .flat_map(|&s| &nfa.states[s].edges)
+                    .filter(|&&(set, _)| member[set])
+                    .map(|&(_, to)| to)
+                    .collect();
+
+                if moved.is_empty() {
+                    table.push(DEAD);
+                    continue;
+                }
+
+                let target = nfa.closure(&moved);
+                let id = *ids.entry(target.clone()).or_insert_with(|| {
+                    subsets.push(target);
+                    subsets.len() - 1
+                });
+                table.push(id);
+            }
+
+            current += 1;
+        }
+
+        Dfa { alphabet, table, accept, start: 0 }
+    }
+
+    // Moore's partition refinement: start from blocks of states with the same
+    // accepting rule and split until every state in a block moves to the
+    // same blocks on every class. Missing transitions count as their own block.
+    pub fn minimize(&self) -> Dfa {
+        let width = self.alphabet.len();
+        let mut block: Vec<usize> = Vec::with_capacity(self.states());
+        let mut labels: HashMap<Option<usize>, usize> = HashMap::new();
+        for &a in &self.accept {
+            let next = labels.len();
+            block.push(*labels.entry(a).or_insert(next));
+        }
+        let mut blocks = labels.len();
+
+        loop {
+            let mut signatures: HashMap<Vec<usize>, usize> = HashMap::new();
+            let mut refined = Vec::with_capacity(block.len());
+
+            for state in 0..self.states() {
+                let mut signature = Vec::with_capacity(width + 1);
+                signature.push(block[state]);
+                for &to in &self.table[state * width..(state + 1) * width] {
+                    signature.push(if to == DEAD { DEAD } else { block[to] });
+                }
+                let next = signatures.len();
+                refined.push(*signatures.entry(signature).or_insert(next));
+            }
+
+            let done = signatures.len() == blocks;
+            blocks = signatures.len();
+            block = refined;
+            if done {
+                break;
+            }
+        }
+
+        let mut table = vec![DEAD; blocks * width];
+        let mut accept = vec![None; blocks];
+        for state in 0..self.states() {
+            let b = block[state];
+            accept[b] = self.accept[state];
+            for class in 0..width {
+                let to = self.table[state * width + class];
+                table[b * width + class] = if to == DEAD { DEAD } else { block[to] };
+            }
+        }
+
+        Dfa { alphabet: self.alphabet.clone(), table, accept, start: block[self.start] }
+    }
+
+    pub fn states(&self) -> usize {
+        self.accept.len()
+    }
+
+    pub fn classes(&self) -> usize {
+        self.alphabet.len()
+    }
+
+    pub fn alphabet(&self) -> &Alphabet {
+        &self.alphabet
+    }
+
+    pub fn transition(&self, state: usize, class: usize) -> usize {
+        self.table[state * self.alphabet.len() + class]
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn accepting(&self, state: usize) -> Option<usize> {
+        self.accept[state]
+    }
+
+    pub fn step(&self, state: usize, c: char) -> usize {
+        match self.alphabet.class_of(c) {
+            Some(class) => self.table[state * self.alphabet.len() + class],
+            None => DEAD,
+        }
+    }
+
+    // Maximal munch: run from `start` until the automaton dies and report the
+    // end of the longest prefix that landed in an accepting state. The lexer
+    // does the same incrementally; this slice version is for the tests.
+    #[cfg(test)]
+    pub fn longest_match(&self, chars: &[char], start: usize) -> Option<(usize, usize)> {
+        let mut state = self.start;
+        let mut last = None;
+
+        for (i, &c) in chars.iter().enumerate().skip(start) {
+            state = self.step(state, c);
+            if state == DEAD {
+                break;
+            }
+            if let Some(rule) = self.accept[state] {
+                last = Some((i + 1, rule));
+            }
+        }
+
+        last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec;
+
+    fn build(src: &str) -> Dfa {
+        Dfa::from_nfa(&Nfa::from_rules(&spec::parse(src).unwrap()))
+    }
+
+    // xorshift, so the corpus is large but reproducible without extra crates
+    fn corpus(seed: u64, count: usize) -> Vec<Vec<char>> {
+        let pool: Vec<char> = "abcdefghijklmnopqrstuvwxyzAZ_0123456789+-*/(), \t\r$.é".chars().collect();
+        let mut x = seed;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+
+        (0..count)
+            .map(|_| {
+                let len = (next() % 24) as usize;
+                (0..len).map(|_| pool[(next() % pool.len() as u64) as usize]).collect()
+            })
+            .collect()
+    }
+
+    fn assert_same_language(a: &Dfa, b: &Dfa, inputs: &[Vec<char>]) {
+        for input in inputs {
+            for start in 0..=input.len() {
+                assert_eq!(
+                    a.longest_match(input, start),
+                    b.longest_match(input, start),
+                    "input {:?} from {}",
+                    input.iter().collect::<String>(),
+                    start
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn minimizes_textbook_example() {
+        let dfa = build("ABB = (a|b)*abb");
+        let min = dfa.minimize();
+        assert_eq!(dfa.states(), 5);
+        assert_eq!(min.states(), 4);
+        assert_same_language(&dfa, &min, &corpus(7, 2_000));
+    }
+
+    #[test]
+    fn minimized_builtin_lexer_accepts_same_tokens() {
+        let dfa = build(crate::lexer::TOKEN_SPEC);
+        let min = dfa.minimize();
+        assert!(min.states() <= dfa.states());
+        assert_same_language(&dfa, &min, &corpus(0x9e37_79b9_7f4a_7c15, 20_000));
+    }
+
+    #[test]
+    fn minimization_keeps_rule_priorities() {
+        let src = "LET = let\nIF = if\nIDENT = [a-z_][a-z0-9_]*\nNUMBER = [0-9]+|[0-9]+x\nSPACE = [ ]+";
+        let dfa = build(src);
+        let min = dfa.minimize();
+        assert!(min.states() < dfa.states());
+        assert_eq!(min.longest_match(&['l', 'e', 't'], 0), Some((3, 0)));
+        assert_eq!(min.longest_match(&['l', 'e', 't', 's'], 0), Some((4, 2)));
+        assert_same_language(&dfa, &min, &corpus(42, 20_000));
+    }
+
+    #[test]
+    fn minimizing_twice_is_stable() {
+        let min = build(crate::lexer::TOKEN_SPEC).minimize();
+        assert_eq!(min.minimize().states(), min.states());
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+
+use crate::unicode;
+
+// Byte offsets into the source the error was reported against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+// Stable error codes. Never renumber these: scripts and docs refer to them.
+// E00xx are syntax errors, E01xx are runtime errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Code {
+    InvalidCharacter = 1,
+    InvalidNumber = 2,
+    UnexpectedEnd = 3,
+    UnexpectedToken = 4,
+    UnbalancedParen = 5,
+    InvalidAssignment = 6,
+    DuplicateParameter = 7,
+    UnterminatedString = 8,
+    InvalidEscape = 9,
+    UnterminatedComment = 10,
+    NestingLimit = 11,
+
+    Undefined = 101,
+    UnknownFunction = 102,
+    ArityMismatch = 103,
+    DivisionByZero = 104,
+    Overflow = 105,
+    RecursionLimit = 106,
+    BuiltinRedefined = 107,
+    InvalidArgument = 108,
+    TypeMismatch = 109,
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "E{:04}", *self as u16)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub code: Code,
+    pub msg: String,
+    pub span: Span,
+    pub labels: Vec<(Span, String)>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Error {
+    pub fn new(code: Code, msg: impl Into<String>, span: Span) -> Self {
+        Self { code, msg: msg.into(), span, labels: Vec::new(), notes: Vec::new(), help: None }
+    }
+
+    // For errors raised below the evaluator, which knows nothing about
+    // source positions; the caller attaches the span with `at`.
+    pub fn bare(code: Code, msg: impl Into<String>) -> Self {
+        Self::new(code, msg, Span::default())
+    }
+
+    pub fn at(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    // A secondary span, underlined with '-' beneath the primary one.
+    pub fn with_label(mut self, span: Span, label: impl Into<String>) -> Self {
+        self.labels.push((span, label.into()));
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error[{}]: {}", self.code, self.msg)
+    }
+}
+
+impl std::error::Error for Error {}
+
+// The char column of `span` in `line`, the padding that puts a marker under
+// it on screen, and how many columns the marker should cover. Tabs are
+// copied so the terminal expands them the same way on both lines.
+fn columns(line: &str, span: Span) -> (usize, String, usize) {
+    let start = span.start.min(line.len());
+    let end = span.end.clamp(start, line.len());
+    let pad = line[..start]
+        .chars()
+        .map(|c| if c == '\t' { "\t".to_string() } else { " ".repeat(unicode::width(c)) })
+        .collect();
+    (line[..start].chars().count(), pad, unicode::str_width(&line[start..end]).max(1))
+}
+
+// The line holding byte `offset`, its 1-based number and its start offset.
+fn locate(source: &str, offset: usize) -> (&str, usize, usize) {
+    let offset = offset.min(source.len());
+    let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+    let line_no = source[..start].matches('\n').count() + 1;
+    (&source[start..end], line_no, start)
+}
+
+// error[E0104]: division by zero
+// --> line 2, col 7
+//   |
+// 2 | 1 + 4 / 0
+//   |       ^
+pub fn render(source: &str, first_line: usize, err: &Error) -> String {
+    let (line, line_no, base) = locate(source, err.span.start);
+    let line_no = line_no + first_line - 1;
+    let line = line.trim_end_matches('\r');
+    let relative = |s: Span| Span::new(s.start.saturating_sub(base), s.end.saturating_sub(base));
+    let (col, pad, width) = columns(line, relative(err.span));
+
+    let gutter = " ".repeat(line_no.to_string().len());
+    let mut out = format!("{}\n", err);
+    out += &format!("{}--> line {}, col {}\n", gutter, line_no, col + 1);
+    out += &format!("{} |\n", gutter);
+    out += &format!("{} | {}\n", line_no, line);
+    out += &format!("{} | {}{}\n", gutter, pad, "^".repeat(width));
+    // Labels on other lines (an unclosed '{' far above) get their own excerpt.
+    for (span, label) in &err.labels {
+        let (other, other_no, other_base) = locate(source, span.start);
+        let other_no = other_no + first_line - 1;
+        if other_no != line_no {
+            let span = Span::new(span.start - other_base, span.end.saturating_sub(other_base));
+            let (_, pad, width) = columns(other.trim_end_matches('\r'), span);
+            out += &format!("{} | ...\n", gutter);
+            out += &format!("{:>w$} | {}\n", other_no, other, w = gutter.len());
+            out += &format!("{} | {}{} {}\n", gutter, pad, "-".repeat(width), label);
+            continue;
+        }
+        let (_, pad, width) = columns(line, relative(*span));
+        out += &format!("{} | {}{} {}\n", gutter, pad, "-".repeat(width), label);
+    }
+    for note in &err.notes {
+        out += &format!("{} = note: {}\n", gutter, note);
+    }
+    if let Some(help) = &err.help {
+        out += &format!("{} = help: {}\n", gutter, help);
+    }
+    out
+}
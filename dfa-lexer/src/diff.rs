@@ -0,0 +1,288 @@
+// Symbolic derivatives, so the REPL can check gradient formulas. Other
+// identifiers are constants. Results are simplified with the rules of real
+// arithmetic (`0 * y` is 0, `a / a` is 1), which the optimizer cannot
+// assume of values that may be strings, floats or unbound. A `/` in the
+// result is exact division, so evaluating it needs --rational or --float;
+// in integer mode `1 / 2` truncates to 0.
+use crate::diag::{Code, Error, Span};
+use crate::eval;
+use crate::parser::Expr;
+use crate::value::{self, Mode, Value};
+
+fn num(n: i64) -> Expr {
+    Expr::Number { value: Value::Int(n), span: Span::default() }
+}
+
+fn binary(op: &str, left: Expr, right: Expr) -> Expr {
+    Expr::Binary { op: op.to_string(), span: Span::default(), left: Box::new(left), right: Box::new(right) }
+}
+
+fn value_of(e: &Expr) -> Option<&Value> {
+    match e {
+        Expr::Number { value, .. } => Some(value),
+        _ => None,
+    }
+}
+
+fn is(e: &Expr, n: i64) -> bool {
+    value_of(e).is_some_and(|v| *v == Value::Int(n))
+}
+
+// Structural equality, ignoring spans.
+fn same(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Number { value: x, .. }, Expr::Number { value: y, .. }) => x == y,
+        (Expr::Ident { name: x, .. }, Expr::Ident { name: y, .. }) => x == y,
+        (Expr::Unary { op: p, operand: x, .. }, Expr::Unary { op: q, operand: y, .. }) => p == q && same(x, y),
+        (Expr::Binary { .. }, Expr::Binary { .. }) => {
+            let ((a, x), (b, y)) = (a.chain(), b.chain());
+            x.len() == y.len() && same(a, b) && x.iter().zip(&y).all(|(x, y)| x.0 == y.0 && same(x.2, y.2))
+        }
+        (Expr::Call { name: f, args: x, .. }, Expr::Call { name: g, args: y, .. }) => {
+            f == g && x.len() == y.len() && x.iter().zip(y).all(|(a, b)| same(a, b))
+        }
+        _ => false,
+    }
+}
+
+fn mentions(e: &Expr, var: &str) -> bool {
+    match e {
+        Expr::Ident { name, .. } => name == var,
+        Expr::Unary { operand, .. } => mentions(operand, var),
+        Expr::Binary { .. } => {
+            let (first, rest) = e.chain();
+            mentions(first, var) || rest.iter().any(|r| mentions(r.2, var))
+        }
+        Expr::Call { args, .. } => args.iter().any(|a| mentions(a, var)),
+        _ => false,
+    }
+}
+
+// Builtins that take numbers and only compute; `print` takes anything.
+fn is_pure(name: &str, arity: usize) -> bool {
+    eval::builtin(name).is_some_and(|(n, _)| n == Some(arity))
+}
+
+fn unsupported(msg: String, span: Span) -> Error {
+    Error::new(Code::InvalidArgument, msg, span).with_note("derivatives are defined for numbers, + - * / and pow")
+}
+
+struct Deriv<'a> {
+    var: &'a str,
+    mode: Mode,
+}
+
+impl Deriv<'_> {
+    // Folds two numbers unless that fails or `/` would round.
+    fn fold(&self, op: &str, a: &Expr, b: &Expr) -> Option<Expr> {
+        let (x, y) = (value_of(a)?, value_of(b)?);
+        let v = value::binary(op, x, y, self.mode).ok()?;
+        if op == "/" && value::binary("*", &v, y, self.mode).ok().as_ref() != Some(x) {
+            return None;
+        }
+        Some(Expr::Number { value: v, span: Span::default() })
+    }
+
+    fn neg(&self, a: Expr) -> Expr {
+        if let Some(value) = value_of(&a).and_then(|v| v.negate(self.mode).ok()) {
+            return Expr::Number { value, span: Span::default() };
+        }
+        match a {
+            Expr::Unary { op, operand, .. } if op == "-" => *operand,
+            Expr::Binary { op, left, right, .. } if op == "-" => self.sub(*right, *left),
+            a => Expr::Unary { op: "-".to_string(), span: Span::default(), operand: Box::new(a) },
+        }
+    }
+
+    fn add(&self, a: Expr, b: Expr) -> Expr {
+        if let Some(e) = self.fold("+", &a, &b) {
+            return e;
+        }
+        match (a, b) {
+            (a, b) if is(&a, 0) => b,
+            (a, b) if is(&b, 0) => a,
+            (a, b) if same(&a, &b) => self.mul(num(2), a),
+            (a, Expr::Unary { op, operand, .. }) if op == "-" => self.sub(a, *operand),
+            (a, b) => binary("+", a, b),
+        }
+    }
+
+    fn sub(&self, a: Expr, b: Expr) -> Expr {
+        if let Some(e) = self.fold("-", &a, &b) {
+            return e;
+        }
+        match (a, b) {
+            (a, b) if is(&b, 0) => a,
+            (a, b) if is(&a, 0) => self.neg(b),
+            (a, b) if same(&a, &b) => num(0),
+            (a, Expr::Unary { op, operand, .. }) if op == "-" => self.add(a, *operand),
+            (a, b) => binary("-", a, b),
+        }
+    }
+
+    // Keeps a number factor in front: `2 * x * 3` becomes `6 * x`.
+    fn mul(&self, a: Expr, b: Expr) -> Expr {
+        if let Some(e) = self.fold("*", &a, &b) {
+            return e;
+        }
+        match (a, b) {
+            (a, b) if is(&a, 0) || is(&b, 0) => num(0),
+            (a, b) if is(&a, 1) => b,
+            (a, b) if is(&b, 1) => a,
+            (a, b) if is(&a, -1) => self.neg(b),
+            (a, b) if is(&b, -1) => self.neg(a),
+            (a, b) if value_of(&a).is_none() && value_of(&b).is_some() => self.mul(b, a),
+            (Expr::Unary { op, operand, .. }, b) if op == "-" => self.neg(self.mul(*operand, b)),
+            (a, Expr::Unary { op, operand, .. }) if op == "-" => self.neg(self.mul(a, *operand)),
+            (a, Expr::Binary { op, left, right, .. }) if op == "*" && value_of(&a).is_some() && value_of(&left).is_some() => {
+                self.mul(self.mul(a, *left), *right)
+            }
+            (a, b) => binary("*", a, b),
+        }
+    }
+
+    fn div(&self, a: Expr, b: Expr) -> Expr {
+        if let Some(e) = self.fold("/", &a, &b) {
+            return e;
+        }
+        match (a, b) {
+            (a, _) if is(&a, 0) => num(0),
+            (a, b) if is(&b, 1) => a,
+            (a, b) if is(&b, -1) => self.neg(a),
+            (a, b) if same(&a, &b) => num(1),
+            (a, b) => binary("/", a, b),
+        }
+    }
+
+    fn pow(&self, base: Expr, exp: Expr) -> Expr {
+        match (base, exp) {
+            (_, exp) if is(&exp, 0) => num(1),
+            (base, exp) if is(&exp, 1) => base,
+            (base, exp) => Expr::Call { name: "pow".to_string(), args: vec![base, exp], span: Span::default() },
+        }
+    }
+
+    // The derivative of `u op v`, given that of `u`, for one of + - * /.
+    fn d_binary(&self, op: &str, u: &Expr, du: Expr, v: &Expr) -> Result<Expr, Error> {
+        Ok(match op {
+            "+" => self.add(du, self.d(v)?),
+            "-" => self.sub(du, self.d(v)?),
+            "*" => {
+                let left = self.mul(du, v.clone());
+                self.add(left, self.mul(u.clone(), self.d(v)?))
+            }
+            "/" if !mentions(v, self.var) => self.div(du, v.clone()),
+            _ => {
+                let top = self.sub(self.mul(du, v.clone()), self.mul(u.clone(), self.d(v)?));
+                self.div(top, self.pow(v.clone(), num(2)))
+            }
+        })
+    }
+
+    fn d(&self, e: &Expr) -> Result<Expr, Error> {
+        Ok(match e {
+            Expr::Number { .. } => num(0),
+            Expr::Ident { name, .. } => num((name == self.var) as i64),
+            Expr::Unary { op, operand, .. } if op == "-" => self.neg(self.d(operand)?),
+            Expr::Binary { .. } => {
+                // Down the run of operators, each node's left operand is the
+                // node before it, whose derivative is already known.
+                let mut nodes = Vec::new();
+                let mut first = e;
+                while let Expr::Binary { op, left, span, .. } = first {
+                    if !matches!(op.as_str(), "+" | "-" | "*" | "/") {
+                        return Err(unsupported(format!("cannot differentiate '{}'", op), *span));
+                    }
+                    nodes.push(first);
+                    first = left;
+                }
+                let mut du = self.d(first)?;
+                for node in nodes.into_iter().rev() {
+                    let Expr::Binary { op, left, right, .. } = node else { unreachable!() };
+                    du = self.d_binary(op, left, du, right)?;
+                }
+                du
+            }
+
+            // Builtin calls that do not involve the variable are constants.
+            // User functions may read globals, so they have no derivative.
+            Expr::Call { name, args, .. } if is_pure(name, args.len()) && !args.iter().any(|a| mentions(a, self.var)) => num(0),
+            Expr::Call { name, args, span } if name == "pow" && args.len() == 2 => {
+                let (u, n) = (&args[0], &args[1]);
+                if mentions(n, self.var) {
+                    return Err(unsupported(format!("cannot differentiate pow with '{}' in the exponent", self.var), *span)
+                        .with_help("that needs a logarithm, which the language does not have"));
+                }
+                let outer = self.mul(n.clone(), self.pow(u.clone(), self.sub(n.clone(), num(1))));
+                self.mul(outer, self.d(u)?)
+            }
+            Expr::Call { name, span, .. } => return Err(unsupported(format!("cannot differentiate '{}'", name), *span)),
+            Expr::Unary { op, span, .. } => return Err(unsupported(format!("cannot differentiate '{}'", op), *span)),
+            _ => return Err(Error::bare(Code::InvalidArgument, "only arithmetic expressions have a derivative")),
+        })
+    }
+}
+
+// The derivative of `expr` with respect to `var`, simplified.
+pub fn diff(expr: &Expr, var: &str, mode: Mode) -> Result<Expr, Error> {
+    Deriv { var, mode }.d(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::Parser;
+    use crate::{Interpreter, fmt};
+
+    fn parse(src: &str) -> Expr {
+        Parser::new(lex(src)).parse_statement().unwrap()
+    }
+
+    fn d(src: &str) -> String {
+        fmt::expr(&diff(&parse(src), "x", Mode::default()).unwrap())
+    }
+
+    #[test]
+    fn simplifies_derivatives() {
+        assert_eq!(d("3 * x * x + 2 * x + 1"), "6 * x + 2");
+        assert_eq!(d("x * x"), "2 * x");
+        assert_eq!(d("pow(x, 3) - y * x"), "3 * pow(x, 2) - y");
+        assert_eq!(d("pow(2 * x + 1, 2)"), "4 * (2 * x + 1)");
+        assert_eq!(d("x / y"), "1 / y");
+        assert_eq!(d("1 / x"), "-1 / pow(x, 2)");
+        assert_eq!(d("x / (x + 1)"), "(x + 1 - x) / pow(x + 1, 2)");
+        assert_eq!(d("-(x * y) + abs(y)"), "-y");
+        assert_eq!(d("x / 2"), "1 / 2");
+        assert_eq!(d("1.5 * x"), "1.5");
+    }
+
+    #[test]
+    fn derivatives_match_finite_differences() {
+        let sources = ["x * x * x - 4 * x", "(x + 1) / (x - 1)", "pow(x * x - 2, 3) / x", "-x / (2 - x) * y"];
+        for src in sources {
+            let derivative = fmt::expr(&diff(&parse(src), "x", Mode::default()).unwrap());
+            let mut interp = Interpreter::new().with_mode(Mode { numeric: crate::Numeric::Float, bigint: false });
+            interp.eval("let x = 3.0\nlet y = 1.5").unwrap();
+            let exact = interp.eval(&derivative).unwrap().to_f64();
+            let at = |x: f64, interp: &mut Interpreter| {
+                interp.eval(&format!("x = {:?}", x)).unwrap();
+                interp.eval(src).unwrap().to_f64()
+            };
+            let h = 1e-6;
+            let estimate = (at(3.0 + h, &mut interp) - at(3.0 - h, &mut interp)) / (2.0 * h);
+            assert!((exact - estimate).abs() < 1e-4, "{}: {} vs {}", derivative, exact, estimate);
+        }
+    }
+
+    #[test]
+    fn rejects_what_has_no_rule() {
+        let err = |src: &str| diff(&parse(src), "x", Mode::default()).unwrap_err();
+        assert_eq!(err("abs(x) + 1").span, Span::new(0, 3));
+        assert_eq!(err("pow(2, x)").code, Code::InvalidArgument);
+        assert_eq!(err("x < 1").span, Span::new(2, 3));
+        assert!(diff(&parse("abs(y)"), "x", Mode::default()).is_ok());
+        assert_eq!(err("x + f(y)").span, Span::new(4, 5));
+        assert_eq!(err("print(y)").code, Code::InvalidArgument);
+    }
+}
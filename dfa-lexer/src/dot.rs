@@ -0,0 +1,123 @@
+use std::fmt::Write;
+
+use crate::dfa::{DEAD, Dfa};
+use crate::nfa::Nfa;
+use crate::regex::{self, CharSet};
+use crate::spec::Rule;
+
+fn show_char(c: char, out: &mut String) {
+    match c {
+        '\n' => out.push_str("\\n"),
+        '\t' => out.push_str("\\t"),
+        '\r' => out.push_str("\\r"),
+        '\\' | ']' | '[' | '-' | '^' => {
+            out.push('\\');
+            out.push(c);
+        }
+        _ if c.is_control() || c == char::MAX => write!(out, "\\u{{{:x}}}", c as u32).unwrap(),
+        _ => out.push(c),
+    }
+}
+
+// Regex-style class syntax, negated when that is shorter. Long runs of
+// non-ASCII ranges, as in the Unicode identifier classes, end in "…".
+fn show_set(set: &CharSet) -> String {
+    let set = regex::normalize(set.clone());
+    if set == [('\0', char::MAX)] {
+        return "any".into();
+    }
+    if let [(lo, hi)] = set[..]
+        && lo == hi
+    {
+        let mut out = String::new();
+        show_char(lo, &mut out);
+        return out;
+    }
+
+    let negated = set.last().is_some_and(|r| r.1 == char::MAX);
+    let ranges = if negated { regex::complement(&set) } else { set };
+    let mut out = String::from(if negated { "[^" } else { "[" });
+    let ascii = ranges.iter().filter(|r| r.0.is_ascii()).count();
+    let shown = if ranges.len() - ascii > 4 { ascii } else { ranges.len() };
+    for &(lo, hi) in &ranges[..shown] {
+        show_char(lo, &mut out);
+        if hi != lo {
+            if regex::next_char(lo) != Some(hi) {
+                out.push('-');
+            }
+            show_char(hi, &mut out);
+        }
+    }
+    if shown < ranges.len() {
+        out.push('…');
+    }
+    out.push(']');
+    out
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+fn header(out: &mut String, name: &str, start: usize) {
+    writeln!(out, "digraph {} {{", name).unwrap();
+    writeln!(out, "  rankdir=LR;").unwrap();
+    writeln!(out, "  node [shape=circle];").unwrap();
+    writeln!(out, "  start [shape=point];").unwrap();
+    writeln!(out, "  start -> {};", start).unwrap();
+}
+
+fn accepting(out: &mut String, state: usize, rule: Option<usize>, rules: &[Rule]) {
+    if let Some(rule) = rule {
+        let label = format!("{}\n{}", state, rules[rule].name);
+        writeln!(out, "  {} [shape=doublecircle, label={}];", state, quote(&label)).unwrap();
+    }
+}
+
+// Epsilon edges are labeled "ε"; other edges by the character set they consume.
+pub fn nfa(nfa: &Nfa, rules: &[Rule]) -> String {
+    let mut out = String::new();
+    header(&mut out, "nfa", nfa.start);
+    for (i, state) in nfa.states.iter().enumerate() {
+        accepting(&mut out, i, nfa.accept[i], rules);
+        for &to in &state.eps {
+            writeln!(out, "  {} -> {} [label=\"ε\", style=dashed];", i, to).unwrap();
+        }
+        for &(set, to) in &state.edges {
+            writeln!(out, "  {} -> {} [label={}];", i, to, quote(&show_set(&nfa.sets[set]))).unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+// One edge per pair of states, labeled with every character that takes it.
+pub fn dfa(dfa: &Dfa, rules: &[Rule], name: &str) -> String {
+    let ranges = dfa.alphabet().ranges();
+    let mut out = String::new();
+    header(&mut out, name, dfa.start());
+
+    for state in 0..dfa.states() {
+        accepting(&mut out, state, dfa.accepting(state), rules);
+
+        let mut edges: Vec<(usize, CharSet)> = Vec::new();
+        for class in 0..dfa.classes() {
+            let to = dfa.transition(state, class);
+            if to == DEAD {
+                continue;
+            }
+            let chars = ranges.iter().filter(|r| r.2 == class).map(|r| (r.0, r.1));
+            match edges.iter_mut().find(|e| e.0 == to) {
+                Some(edge) => edge.1.extend(chars),
+                None => edges.push((to, chars.collect())),
+            }
+        }
+        edges.sort_by_key(|e| e.0);
+
+        for (to, set) in edges {
+            writeln!(out, "  {} -> {} [label={}];", state, to, quote(&show_set(&set))).unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
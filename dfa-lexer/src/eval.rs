@@ -0,0 +1,403 @@
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::diag::{Code, Error, Span};
+use crate::optimize::optimize;
+use crate::parser::Expr;
+use crate::value::{self, Mode, Value};
+use crate::vm;
+
+type Builtin = fn(&[Value], Mode) -> Result<Value, Error>;
+
+// An arity of `None` takes any number of arguments of any type; the others
+// take exactly that many numbers.
+const BUILTINS: &[(&str, Option<usize>, Builtin)] = &[
+    ("min", Some(2), |a, _| pick(&a[0], &a[1], Ordering::Less)),
+    ("max", Some(2), |a, _| pick(&a[0], &a[1], Ordering::Greater)),
+    ("abs", Some(1), |a, mode| a[0].abs(mode)),
+    ("pow", Some(2), |a, mode| a[0].pow(&a[1], mode)),
+    ("gcd", Some(2), |a, mode| a[0].gcd(&a[1], mode)),
+    ("clamp", Some(3), builtin_clamp),
+    ("print", None, builtin_print),
+];
+
+pub(crate) fn builtin(name: &str) -> Option<(Option<usize>, Builtin)> {
+    BUILTINS.iter().find(|b| b.0 == name).map(|b| (b.1, b.2))
+}
+
+pub(crate) fn builtins() -> impl Iterator<Item = (&'static str, Option<usize>)> {
+    BUILTINS.iter().map(|b| (b.0, b.1))
+}
+
+fn compare(a: &Value, b: &Value) -> Result<Ordering, Error> {
+    a.partial_cmp(b)
+        .ok_or_else(|| Error::bare(Code::InvalidArgument, format!("cannot compare {} and {}", a, b)))
+}
+
+fn pick(a: &Value, b: &Value, want: Ordering) -> Result<Value, Error> {
+    Ok(if compare(b, a)? == want { b.clone() } else { a.clone() })
+}
+
+fn builtin_clamp(a: &[Value], _: Mode) -> Result<Value, Error> {
+    if compare(&a[1], &a[2])? == Ordering::Greater {
+        let msg = format!("clamp bounds are reversed ({} > {})", a[1], a[2]);
+        return Err(Error::bare(Code::InvalidArgument, msg).with_help("call it as clamp(value, low, high)"));
+    }
+    let low = pick(&a[0], &a[1], Ordering::Greater)?;
+    pick(&low, &a[2], Ordering::Less)
+}
+
+// Writes its arguments to stdout separated by spaces, strings unquoted.
+fn builtin_print(a: &[Value], _: Mode) -> Result<Value, Error> {
+    let line: Vec<String> = a.iter().map(Value::to_string).collect();
+    println!("{}", line.join(" "));
+    Ok(Value::Unit)
+}
+
+// `code` caches the body's bytecode the first time the VM calls it.
+#[derive(Debug)]
+pub(crate) struct Function {
+    pub(crate) params: Vec<String>,
+    pub(crate) body: Rc<Expr>,
+    pub(crate) code: OnceCell<Rc<vm::Chunk>>,
+}
+
+impl Function {
+    pub(crate) fn new(params: Vec<String>, body: Rc<Expr>) -> Self {
+        Self { params, body, code: OnceCell::new() }
+    }
+}
+
+pub(crate) enum Callee {
+    Builtin(Builtin),
+    User(Rc<Function>),
+}
+
+// Both backends stop here.
+const MAX_CALL_DEPTH: usize = 200;
+
+// The tree walker recurses natively, once per `eval` of a subexpression, and
+// a debug build takes about 3 KB of stack per level. Calls are refused once
+// this many are live; the body of the last one adds at most `MAX_NESTING`
+// more, and together they fit in a main thread's 8 MiB.
+const MAX_EVAL_DEPTH: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    Tree,
+    Vm,
+}
+
+// `scopes[0]` holds the globals. Blocks push a scope and pop it on exit; a
+// function call starts a new frame at `frame_base`, so the body sees its
+// own scopes and the globals but nothing from its caller. The VM keeps
+// block and function locals in its own slots and only uses `scopes[0]`.
+#[derive(Debug)]
+pub struct Env {
+    pub(crate) scopes: Vec<HashMap<String, Value>>,
+    pub(crate) frame_base: usize,
+    pub(crate) calls: usize,
+    pub(crate) depth: usize,
+    pub(crate) functions: HashMap<String, Rc<Function>>,
+    pub(crate) mode: Mode,
+    pub(crate) backend: Backend,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            frame_base: 0,
+            calls: 0,
+            depth: 0,
+            functions: HashMap::new(),
+            mode: Mode::default(),
+            backend: Backend::default(),
+        }
+    }
+}
+
+impl Env {
+    fn lookup(&self, name: &str) -> Option<usize> {
+        (self.frame_base..self.scopes.len())
+            .rev()
+            .chain(0..1)
+            .find(|&i| self.scopes[i].contains_key(name))
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Value> {
+        self.lookup(name).map(|i| self.scopes[i][name].clone())
+    }
+
+    pub(crate) fn define(&mut self, name: &str, value: Value) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), value);
+    }
+
+    // Assignment updates the nearest visible binding, or defines one in the
+    // innermost scope if there is none.
+    pub(crate) fn assign(&mut self, name: &str, value: Value) {
+        let scope = self.lookup(name).unwrap_or(self.scopes.len() - 1);
+        self.scopes[scope].insert(name.to_string(), value);
+    }
+
+    // Forgets every binding and function but keeps the numeric mode and backend.
+    pub(crate) fn reset(&mut self) {
+        *self = Env { mode: self.mode, backend: self.backend, ..Env::default() };
+    }
+}
+
+fn type_error(msg: String, span: Span) -> Error {
+    Error::new(Code::TypeMismatch, msg, span)
+}
+
+pub(crate) fn expect_bool(v: Value, what: &str, span: Span) -> Result<bool, Error> {
+    match v {
+        Value::Bool(b) => Ok(b),
+        other => Err(type_error(format!("{} must be a bool, found {}", what, other.type_name()), span)),
+    }
+}
+
+fn eval_block(stmts: &[Expr], env: &mut Env) -> Result<Value, Error> {
+    env.scopes.push(HashMap::new());
+    let depth = env.scopes.len();
+
+    let mut last = Value::Unit;
+    let mut result = Ok(());
+    for stmt in stmts {
+        match eval(stmt, env) {
+            Ok(v) => last = if stmt.is_statement() { Value::Unit } else { v },
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+
+    env.scopes.truncate(depth - 1);
+    result.map(|_| last)
+}
+
+// Pieces of evaluation shared by the tree walker and the bytecode VM, so the
+// two backends report exactly the same errors.
+pub(crate) fn literal(value: &Value, span: Span, mode: Mode) -> Result<Value, Error> {
+    if let Value::Big(_) = value
+        && !mode.bigint
+    {
+        return Err(Error::new(Code::Overflow, "integer literal too large", span)
+            .with_help("run with --bigint for arbitrary precision"));
+    }
+    Ok(value.clone())
+}
+
+// Parsing records the real error; callers are not meant to run what is left.
+pub(crate) fn syntax_error(span: Span) -> Error {
+    Error::new(Code::UnexpectedToken, "cannot run a statement that failed to parse", span)
+}
+
+pub(crate) fn load(env: &Env, name: &str, span: Span) -> Result<Value, Error> {
+    env.get(name).ok_or_else(|| {
+        Error::new(Code::Undefined, format!("undefined '{}'", name), span)
+            .with_help(format!("define it first with `let {} = ...`", name))
+    })
+}
+
+pub(crate) fn define_fn(env: &mut Env, name: &str, function: Rc<Function>, span: Span) -> Result<(), Error> {
+    if builtin(name).is_some() {
+        let msg = format!("cannot redefine builtin '{}'", name);
+        return Err(Error::new(Code::BuiltinRedefined, msg, span).with_help("pick a different name"));
+    }
+    env.functions.insert(name.to_string(), function);
+    Ok(())
+}
+
+// Resolves the callee and checks arity before any argument is evaluated.
+pub(crate) fn resolve_call(env: &Env, name: &str, argc: usize, span: Span) -> Result<Callee, Error> {
+    let (arity, callee) = match (builtin(name), env.functions.get(name)) {
+        (Some((arity, f)), _) => (arity, Callee::Builtin(f)),
+        (None, Some(f)) => (Some(f.params.len()), Callee::User(f.clone())),
+        (None, None) => {
+            let names: Vec<&str> = BUILTINS.iter().map(|b| b.0).collect();
+            return Err(Error::new(Code::UnknownFunction, format!("unknown function '{}'", name), span)
+                .with_note(format!("builtins are {}", names.join(", ")))
+                .with_help(format!("define it with `fn {}(...) = ...`", name)));
+        }
+    };
+
+    if let Some(arity) = arity
+        && argc != arity
+    {
+        let plural = if arity == 1 { "" } else { "s" };
+        let given = if argc == 1 { "was" } else { "were" };
+        let msg = format!("function '{}' takes {} argument{} but {} {} given", name, arity, plural, argc, given);
+        let err = Error::new(Code::ArityMismatch, msg, span);
+        return Err(match &callee {
+            Callee::User(f) => err.with_note(format!("'{}' is defined as {}({})", name, name, f.params.join(", "))),
+            Callee::Builtin(_) => err,
+        });
+    }
+    Ok(callee)
+}
+
+pub(crate) fn call_builtin(name: &str, f: Builtin, values: &[Value], span: Span, mode: Mode) -> Result<Value, Error> {
+    let variadic = builtin(name).is_some_and(|b| b.0.is_none());
+    if let Some(v) = values.iter().find(|v| !variadic && !v.is_numeric()) {
+        let msg = format!("'{}' expects numbers, found {}", name, v.type_name());
+        return Err(type_error(msg, span));
+    }
+    f(values, mode).map_err(|e| e.at(span))
+}
+
+// Counts a user call against MAX_CALL_DEPTH. The caller decrements
+// `env.calls` again when the call returns.
+pub(crate) fn count_call(env: &mut Env, span: Span) -> Result<(), Error> {
+    if env.calls >= MAX_CALL_DEPTH {
+        return Err(Error::new(Code::RecursionLimit, "recursion limit exceeded", span)
+            .with_note(format!("calls may nest at most {} deep", MAX_CALL_DEPTH)));
+    }
+    env.calls += 1;
+    Ok(())
+}
+
+// Opens a frame for a user function and returns the caller's frame base,
+// which `leave_call` restores.
+fn enter_call(env: &mut Env, f: &Function, values: Vec<Value>, span: Span) -> Result<usize, Error> {
+    if env.depth >= MAX_EVAL_DEPTH {
+        return Err(Error::new(Code::RecursionLimit, "recursion limit exceeded", span)
+            .with_note(format!("calls and the expressions around them may nest at most {} deep", MAX_EVAL_DEPTH)));
+    }
+    count_call(env, span)?;
+    let saved_base = env.frame_base;
+    env.frame_base = env.scopes.len();
+    env.scopes.push(f.params.iter().cloned().zip(values).collect());
+    Ok(saved_base)
+}
+
+fn leave_call(env: &mut Env, saved_base: usize) {
+    env.calls -= 1;
+    env.scopes.truncate(env.frame_base);
+    env.frame_base = saved_base;
+}
+
+// Spans inside a function body may refer to an earlier input, so errors
+// escaping the outermost call point at the call site instead.
+pub(crate) fn relocate(err: Error, span: Span, name: &str) -> Error {
+    err.at(span).with_note(format!("raised inside '{}'", name))
+}
+
+fn eval_bind(name: &str, value: &Expr, bind: fn(&mut Env, &str, Value), env: &mut Env) -> Result<Value, Error> {
+    let v = eval(value, env)?;
+    bind(env, name, v.clone());
+    Ok(v)
+}
+
+fn eval_call(name: &str, args: &[Expr], span: Span, env: &mut Env) -> Result<Value, Error> {
+    let callee = resolve_call(env, name, args.len(), span)?;
+    let values = args.iter().map(|a| eval(a, env)).collect::<Result<Vec<_>, _>>()?;
+
+    match callee {
+        Callee::Builtin(f) => call_builtin(name, f, &values, span, env.mode),
+        Callee::User(f) => {
+            let saved_base = enter_call(env, &f, values, span)?;
+            let result = eval(&f.body, env);
+            leave_call(env, saved_base);
+
+            if env.calls == 0 {
+                return result.map_err(|e| relocate(e, span, name));
+            }
+            result
+        }
+    }
+}
+
+fn eval_unary(op: &str, span: Span, operand: &Expr, env: &mut Env) -> Result<Value, Error> {
+    let v = eval(operand, env)?;
+
+    match op {
+        "-" => v.negate(env.mode).map_err(|e| e.at(span)),
+        "!" => Ok(Value::Bool(!expect_bool(v, "operand of '!'", span)?)),
+        _ => Err(Error::new(Code::InvalidArgument, format!("unknown operator '{}'", op), span)),
+    }
+}
+
+// `&&` and `||` only evaluate the right operand when the left one does not
+// decide the result.
+fn eval_logic(op: &str, span: Span, l: Value, right: &Expr, env: &mut Env) -> Result<Value, Error> {
+    let l = expect_bool(l, &format!("left operand of '{}'", op), span)?;
+    if l == (op == "||") {
+        return Ok(Value::Bool(l));
+    }
+    let r = expect_bool(eval(right, env)?, &format!("right operand of '{}'", op), span)?;
+    Ok(Value::Bool(r))
+}
+
+fn eval_binary(op: &str, span: Span, l: Value, right: &Expr, env: &mut Env) -> Result<Value, Error> {
+    let r = eval(right, env)?;
+    value::binary(op, &l, &r, env.mode).map_err(|e| e.at(span))
+}
+
+fn eval_chain(expr: &Expr, env: &mut Env) -> Result<Value, Error> {
+    let (first, rest) = expr.chain();
+    let mut acc = eval(first, env)?;
+    for (op, span, right) in rest {
+        acc = match op {
+            "&&" | "||" => eval_logic(op, span, acc, right, env)?,
+            _ => eval_binary(op, span, acc, right, env)?,
+        };
+    }
+    Ok(acc)
+}
+
+fn eval_if(cond: &Expr, then: &Expr, otherwise: Option<&Expr>, span: Span, env: &mut Env) -> Result<Value, Error> {
+    if expect_bool(eval(cond, env)?, "condition of 'if'", span)? {
+        eval(then, env)
+    } else if let Some(otherwise) = otherwise {
+        eval(otherwise, env)
+    } else {
+        Ok(Value::Unit)
+    }
+}
+
+fn eval_while(cond: &Expr, body: &Expr, span: Span, env: &mut Env) -> Result<Value, Error> {
+    while expect_bool(eval(cond, env)?, "condition of 'while'", span)? {
+        eval(body, env)?;
+    }
+    Ok(Value::Unit)
+}
+
+// Every arm with temporaries lives in its own function, which keeps each
+// `eval` frame small; `MAX_EVAL_DEPTH` bounds how many are live at once.
+pub fn eval(expr: &Expr, env: &mut Env) -> Result<Value, Error> {
+    env.depth += 1;
+    let result = match expr {
+        Expr::Number { value, span } => literal(value, *span, env.mode),
+        Expr::Bool(b) => Ok(Value::Bool(*b)),
+        Expr::Str(s) => Ok(Value::Str(s.clone())),
+        Expr::Ident { name, span } => load(env, name, *span),
+        Expr::Let { name, value, .. } => eval_bind(name, value, Env::define, env),
+        Expr::Assign { name, value, .. } => eval_bind(name, value, Env::assign, env),
+        Expr::FnDef { name, params, body, span } => {
+            define_fn(env, name, Rc::new(Function::new(params.clone(), body.clone())), *span).map(|_| Value::Unit)
+        }
+        Expr::Call { name, args, span } => eval_call(name, args, *span, env),
+        Expr::Unary { op, span, operand } => eval_unary(op, *span, operand, env),
+        Expr::Binary { .. } => eval_chain(expr, env),
+        Expr::Block { stmts, .. } => eval_block(stmts, env),
+        Expr::Error { span } => Err(syntax_error(*span)),
+        Expr::If { cond, then, otherwise, span } => eval_if(cond, then, otherwise.as_deref(), *span, env),
+        Expr::While { cond, body, span } => eval_while(cond, body, *span, env),
+    };
+    env.depth -= 1;
+    result
+}
+
+// Optimizes one parsed statement and runs it on the backend `env` selects.
+pub fn execute(stmt: &Expr, env: &mut Env) -> Result<Value, Error> {
+    let stmt = optimize(stmt, env.mode)?;
+    match env.backend {
+        Backend::Tree => eval(&stmt, env),
+        Backend::Vm => vm::run(Rc::new(vm::compile(&stmt)), env),
+    }
+}
@@ -0,0 +1,299 @@
+// Canonical source text for a script: one statement per line, four-space
+// indentation, single spaces around binary operators and only the
+// parentheses that precedence requires. Comments on a line of their own stay
+// there; any other comment follows the statement it sat in.
+use std::fmt::Write;
+
+use crate::diag::{Error, Span};
+use crate::lexer::{Lexer, Token, TokenKind};
+use crate::parser::{Expr, PREFIX_BP, Parser, infix_binding_power};
+
+const INDENT: &str = "    ";
+
+struct Comment {
+    span: Span,
+    text: String,
+    done: bool,
+}
+
+struct Formatter<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    comments: Vec<Comment>,
+}
+
+// Refuses to format a script with syntax errors and returns all of them.
+pub fn format(source: &str) -> Result<String, Vec<Error>> {
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    for tok in Lexer::new(source.as_bytes()).with_comments() {
+        let tok = tok.expect("reading from memory");
+        match tok.kind {
+            TokenKind::Comment(text) => comments.push(Comment { span: tok.span, text: text.trim_end().to_string(), done: false }),
+            _ => tokens.push(tok),
+        }
+    }
+
+    let (stmts, errors) = Parser::new(tokens.clone()).parse_program();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut f = Formatter { src: source, tokens, comments };
+    let mut out = String::new();
+    f.list(&stmts, 0, source.len(), 0, &mut out);
+    Ok(out)
+}
+
+// Source text for a tree built in code, such as a derivative, which has
+// numbers but no blocks or comments. Numbers are printed from their values.
+pub fn expr(e: &Expr) -> String {
+    Formatter { src: "", tokens: Vec::new(), comments: Vec::new() }.expr(e, 0)
+}
+
+fn is_separator(tok: &Token) -> bool {
+    matches!(tok.kind, TokenKind::Newline | TokenKind::Semi)
+}
+
+// Blocks holding one of these print on a single line.
+fn is_simple(e: &Expr) -> bool {
+    match e {
+        Expr::Let { value, .. } | Expr::Assign { value, .. } => is_simple(value),
+        Expr::Unary { operand, .. } => is_simple(operand),
+        Expr::Binary { .. } => {
+            let (first, rest) = e.chain();
+            is_simple(first) && rest.iter().all(|r| is_simple(r.2))
+        }
+        Expr::Call { args, .. } => args.iter().all(is_simple),
+        Expr::Block { .. } | Expr::If { .. } | Expr::While { .. } | Expr::FnDef { .. } => false,
+        _ => true,
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            _ if c.is_control() => write!(out, "\\u{{{:x}}}", c as u32).unwrap(),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Whether the source had an empty line between byte offsets `last` and
+// `start`. Nothing gets a blank line above it at the top of a list.
+fn blank_line(src: &str, last: Option<usize>, start: usize) -> bool {
+    last.is_some_and(|last| src[last..start].matches('\n').count() > 1)
+}
+
+impl Formatter<'_> {
+    // Byte ranges of the statements between `from` and `to`, cut the way
+    // `Statements` cuts them: at ';' or at a newline outside brackets, unless
+    // the line ends in an operator or the next one starts with `else`.
+    fn extents(&self, from: usize, to: usize) -> Vec<(usize, usize)> {
+        let toks: Vec<&Token> = self.tokens.iter().filter(|t| from <= t.span.start && t.span.end <= to).collect();
+        let mut extents = Vec::new();
+        let mut i = 0;
+        while i < toks.len() {
+            if is_separator(toks[i]) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            let mut depth = 0usize;
+            while i < toks.len() {
+                match toks[i].kind {
+                    TokenKind::LParen | TokenKind::LBrace => depth += 1,
+                    TokenKind::RParen | TokenKind::RBrace => depth = depth.saturating_sub(1),
+                    TokenKind::Semi if depth == 0 => break,
+                    TokenKind::Newline if depth == 0 => {
+                        let code = |t: &&&Token| !matches!(t.kind, TokenKind::Newline);
+                        let operator = toks[start..i].iter().rev().find(code).is_some_and(|t| matches!(t.kind, TokenKind::Operator(_)));
+                        let more = toks[i..].iter().find(code).is_some_and(|t| matches!(t.kind, TokenKind::Else));
+                        if !operator && !more {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            let last = toks[start..i].iter().rev().find(|t| !is_separator(t)).unwrap();
+            extents.push((toks[start].span.start, last.span.end));
+        }
+        extents
+    }
+
+    // Writes the comments that start between `from` and `to` on lines of
+    // their own and returns where the last one ends.
+    fn own_lines(&mut self, from: usize, to: usize, mut last: Option<usize>, depth: usize, out: &mut String) -> Option<usize> {
+        for c in self.comments.iter_mut().filter(|c| !c.done && from <= c.span.start && c.span.start < to) {
+            if blank_line(self.src, last, c.span.start) {
+                out.push('\n');
+            }
+            writeln!(out, "{}{}", INDENT.repeat(depth), c.text).unwrap();
+            c.done = true;
+            last = Some(c.span.end);
+        }
+        last
+    }
+
+    // Writes `stmts`, which lie between byte offsets `from` and `to`, one
+    // per line with the comments among them.
+    fn list(&mut self, stmts: &[Expr], from: usize, to: usize, depth: usize, out: &mut String) {
+        let extents = self.extents(from, to);
+        let src = self.src;
+        let mut last = None;
+        for (i, (stmt, &(start, end))) in stmts.iter().zip(&extents).enumerate() {
+            last = self.own_lines(from, start, last, depth, out);
+            if blank_line(src, last, start) {
+                out.push('\n');
+            }
+            let text = self.expr(stmt, depth);
+            out.push_str(&INDENT.repeat(depth));
+            out.push_str(&text);
+
+            // Comments inside the statement, or after it on its last line.
+            let next = extents.get(i + 1).map_or(to, |e| e.0);
+            let mut trailing_end = end;
+            for c in self.comments.iter_mut().filter(|c| !c.done && start <= c.span.start && c.span.start < next) {
+                if c.span.start < end || !src[end..c.span.start].contains('\n') {
+                    out.push(' ');
+                    out.push_str(&c.text);
+                    c.done = true;
+                    trailing_end = trailing_end.max(c.span.end);
+                }
+            }
+            out.push('\n');
+            last = Some(trailing_end);
+        }
+        self.own_lines(from, to, last, depth, out);
+    }
+
+    fn block(&mut self, stmts: &[Expr], span: Span, depth: usize) -> String {
+        let (from, to) = (span.start + 1, span.end - 1);
+        let commented = self.comments.iter().any(|c| !c.done && from <= c.span.start && c.span.start < to);
+        match stmts {
+            [] if !commented => "{}".to_string(),
+            [stmt] if !commented && is_simple(stmt) => format!("{{ {} }}", self.expr(stmt, depth)),
+            _ => {
+                let mut out = String::from("{\n");
+                self.list(stmts, from, to, depth + 1, &mut out);
+                out + &INDENT.repeat(depth) + "}"
+            }
+        }
+    }
+
+    // `e` as an operand that stays in place only if it binds at least as
+    // tightly as `min`.
+    fn operand(&mut self, e: &Expr, min: u8, depth: usize) -> String {
+        let text = self.expr(e, depth);
+        match e {
+            Expr::Binary { op, .. } if infix_binding_power(op).is_some_and(|(left, _)| left < min) => format!("({})", text),
+            _ => text,
+        }
+    }
+
+    // The left operand of each operator is everything before it, which
+    // needs parentheses when the previous operator binds more loosely.
+    fn chain(&mut self, e: &Expr, depth: usize) -> String {
+        let (first, rest) = e.chain();
+        let mut out = self.expr(first, depth);
+        let mut prev: Option<&str> = None;
+        for (op, _, right) in rest {
+            let (l, r) = infix_binding_power(op).unwrap_or_default();
+            if prev.and_then(infix_binding_power).is_some_and(|(left, _)| left < l) {
+                out.insert(0, '(');
+                out.push(')');
+            }
+            write!(out, " {} {}", op, self.operand(right, r, depth)).unwrap();
+            prev = Some(op);
+        }
+        out
+    }
+
+    fn expr(&mut self, e: &Expr, depth: usize) -> String {
+        match e {
+            Expr::Number { value, span } => match self.src.get(span.start..span.end) {
+                Some(text) if !text.is_empty() => text.to_string(),
+                _ => value.to_string(),
+            },
+            Expr::Error { span } => self.src[span.start..span.end].to_string(),
+            Expr::Bool(b) => b.to_string(),
+            Expr::Str(s) => quote(s),
+            Expr::Ident { name, .. } => name.clone(),
+            Expr::Let { name, value, .. } => format!("let {} = {}", name, self.expr(value, depth)),
+            Expr::Assign { name, value, .. } => format!("{} = {}", name, self.expr(value, depth)),
+            Expr::FnDef { name, params, body, .. } => {
+                let sep = if matches!(**body, Expr::Block { .. }) { " " } else { " = " };
+                format!("fn {}({}){}{}", name, params.join(", "), sep, self.expr(body, depth))
+            }
+            Expr::Call { name, args, .. } => {
+                let args: Vec<String> = args.iter().map(|a| self.expr(a, depth)).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            Expr::Unary { op, operand, .. } => format!("{}{}", op, self.operand(operand, PREFIX_BP, depth)),
+            Expr::Binary { .. } => self.chain(e, depth),
+            Expr::Block { stmts, span } => self.block(stmts, *span, depth),
+            Expr::If { cond, then, otherwise, .. } => {
+                let mut out = format!("if {} {}", self.expr(cond, depth), self.expr(then, depth));
+                if let Some(otherwise) = otherwise {
+                    out.push_str(" else ");
+                    out.push_str(&self.expr(otherwise, depth));
+                }
+                out
+            }
+            Expr::While { cond, body, .. } => format!("while {} {}", self.expr(cond, depth), self.expr(body, depth)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(src: &str) -> String {
+        let out = format(src).unwrap_or_else(|e| panic!("{:?}", e));
+        assert_eq!(format(&out).unwrap(), out, "formatting is not idempotent for {:?}", src);
+        out
+    }
+
+    #[test]
+    fn canonical_spacing_and_parentheses() {
+        assert_eq!(fmt("let   x=(1+2)*3;y =x-(4-5)"), "let x = (1 + 2) * 3\ny = x - (4 - 5)\n");
+        assert_eq!(fmt("((a*b))+c/(d)"), "a * b + c / d\n");
+        assert_eq!(fmt("-(a+b) == !(c) || (d && e)"), "-(a + b) == !c || d && e\n");
+        assert_eq!(fmt("0x1F +1.50e3"), "0x1F + 1.50e3\n");
+        assert_eq!(fmt("print( \"a\\tb\\\"\" ,\n  2 )"), "print(\"a\\tb\\\"\", 2)\n");
+        assert_eq!(fmt(""), "");
+    }
+
+    #[test]
+    fn blocks_indent_and_short_ones_stay_on_one_line() {
+        let src = "fn f(a,b)=a+b\nfn g(n){if n<2{n}else{\nlet m=n-1\ng(m)*n}}\nwhile false{}";
+        let expected = "fn f(a, b) = a + b\nfn g(n) {\n    if n < 2 { n } else {\n        let m = n - 1\n        g(m) * n\n    }\n}\nwhile false {}\n";
+        assert_eq!(fmt(src), expected);
+        assert_eq!(fmt("if a { 1 }\n\n\nelse if b { 2 } else { 3 }"), "if a { 1 } else if b { 2 } else { 3 }\n");
+    }
+
+    #[test]
+    fn keeps_comments_and_single_blank_lines() {
+        let src = "# header\n\n\nlet x = 1  # one\nlet y = (2 /* two */ + 3)\n\nfn f() {\n  # inside\n  x\n  /* before close */\n}\n# end\n";
+        let expected = "# header\n\nlet x = 1 # one\nlet y = 2 + 3 /* two */\n\nfn f() {\n    # inside\n    x\n    /* before close */\n}\n# end\n";
+        assert_eq!(fmt(src), expected);
+        assert_eq!(fmt("{ # only a comment\n}"), "{\n    # only a comment\n}\n");
+    }
+
+    #[test]
+    fn refuses_scripts_with_syntax_errors() {
+        let errors = format("let = 1\nx = (2\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+}
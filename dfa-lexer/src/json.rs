@@ -0,0 +1,295 @@
+// Just enough JSON for the language server: a value type, a parser and
+// compact printing through `Display`.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    // Keys keep their order, so output is stable.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<'a>(pairs: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+        Json::Object(pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    // Follows a path of object keys; missing keys give None.
+    pub fn get(&self, path: &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |json, key| match json {
+            Json::Object(pairs) => pairs.iter().find(|p| p.0 == *key).map(|p| &p.1),
+            _ => None,
+        })
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::Str(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::Str(s)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+fn write_str(s: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            _ if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Json::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Json::Number(_) => f.write_str("null"),
+            Json::Str(s) => write_str(s, f),
+            Json::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(pairs) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_str(key, f)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, what: &str) -> String {
+        format!("{} at byte {}", what, self.pos)
+    }
+
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_space();
+        let value = match self.peek() {
+            None => return Err(self.error("unexpected end of input")),
+            Some('n') => self.keyword("null", Json::Null)?,
+            Some('t') => self.keyword("true", Json::Bool(true))?,
+            Some('f') => self.keyword("false", Json::Bool(false))?,
+            Some('"') => Json::Str(self.string()?),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_space();
+                if !self.eat(']') {
+                    loop {
+                        items.push(self.value()?);
+                        self.skip_space();
+                        if self.eat(']') {
+                            break;
+                        }
+                        if !self.eat(',') {
+                            return Err(self.error("expected ',' or ']'"));
+                        }
+                    }
+                }
+                Json::Array(items)
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut pairs = Vec::new();
+                self.skip_space();
+                if !self.eat('}') {
+                    loop {
+                        self.skip_space();
+                        if self.peek() != Some('"') {
+                            return Err(self.error("expected a key"));
+                        }
+                        let key = self.string()?;
+                        self.skip_space();
+                        if !self.eat(':') {
+                            return Err(self.error("expected ':'"));
+                        }
+                        pairs.push((key, self.value()?));
+                        self.skip_space();
+                        if self.eat('}') {
+                            break;
+                        }
+                        if !self.eat(',') {
+                            return Err(self.error("expected ',' or '}'"));
+                        }
+                    }
+                }
+                Json::Object(pairs)
+            }
+            Some(_) => {
+                let rest = &self.text[self.pos..];
+                let len = rest.find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E')).unwrap_or(rest.len());
+                let n = rest[..len].parse::<f64>().map_err(|_| self.error("invalid number"))?;
+                self.pos += len;
+                Json::Number(n)
+            }
+        };
+        Ok(value)
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or_else(|| self.error("short \\u escape"))?;
+        let n = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(n)
+    }
+
+    // Called at the opening quote.
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let e = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    out.push(match e {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let mut n = self.hex4()?;
+                            // A surrogate pair spells one char outside the BMP.
+                            if (0xd800..0xdc00).contains(&n) && self.text[self.pos..].starts_with("\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                n = 0x10000 + ((n - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(n).unwrap_or('\u{fffd}')
+                        }
+                        c @ ('"' | '\\' | '/') => c,
+                        _ => return Err(self.error("invalid escape")),
+                    });
+                }
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut reader = Reader { text, pos: 0 };
+    let value = reader.value()?;
+    reader.skip_space();
+    if reader.pos < text.len() {
+        return Err(reader.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_prints_back() {
+        let text = r#"{"id":1,"params":{"text":"a\"b\né😀","list":[true,null,-2.5,[]]}}"#;
+        let json = parse(text).unwrap();
+        assert_eq!(json.get(&["params", "text"]).and_then(Json::as_str), Some("a\"b\né😀"));
+        assert_eq!(json.get(&["id"]).and_then(Json::as_usize), Some(1));
+        assert_eq!(parse(&json.to_string()).unwrap(), json);
+        assert!(parse("{\"a\":}").is_err());
+        assert!(parse("[1] 2").is_err());
+    }
+}
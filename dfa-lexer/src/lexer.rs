@@ -0,0 +1,536 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::sync::OnceLock;
+
+use crate::bigint::BigInt;
+use crate::dfa::{DEAD, Dfa};
+use crate::diag::{Code, Error, Span};
+use crate::nfa::Nfa;
+use crate::spec;
+use crate::value::Value;
+
+#[derive(Debug, Clone)]
+pub enum TokenKind {
+    Ident(String),
+    Number(Value),
+    Str(String),
+    Operator(String),
+    // Only from a lexer built `with_comments`; the parser never sees these.
+    Comment(String),
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semi,
+    Newline,
+    Assign,
+    Let,
+    Fn,
+    If,
+    Else,
+    While,
+    True,
+    False,
+    // Lexing never stops early; bad input becomes a token the parser reports.
+    Invalid(Error),
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    // Byte offsets; `char_span` is the same range counted in chars.
+    pub span: Span,
+    pub char_span: Span,
+    // 1-based; columns count chars, not bytes.
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tag {
+    Skip,
+    Comment,
+    Ident,
+    Number,
+    RadixNumber,
+    Float,
+    Str,
+    OpenStr,
+    OpenComment,
+    Operator,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semi,
+    Newline,
+    Assign,
+    Let,
+    Fn,
+    If,
+    Else,
+    While,
+    True,
+    False,
+}
+
+impl Tag {
+    fn from_name(name: &str) -> Option<Tag> {
+        Some(match name {
+            "SPACE" => Tag::Skip,
+            "COMMENT" => Tag::Comment,
+            "IDENT" => Tag::Ident,
+            "NUMBER" => Tag::Number,
+            "RADIX" => Tag::RadixNumber,
+            "FLOAT" => Tag::Float,
+            "STRING" => Tag::Str,
+            "OPEN_STRING" => Tag::OpenStr,
+            "OPEN_COMMENT" => Tag::OpenComment,
+            "OPERATOR" => Tag::Operator,
+            "LPAREN" => Tag::LParen,
+            "RPAREN" => Tag::RParen,
+            "LBRACE" => Tag::LBrace,
+            "RBRACE" => Tag::RBrace,
+            "COMMA" => Tag::Comma,
+            "SEMI" => Tag::Semi,
+            "NEWLINE" => Tag::Newline,
+            "ASSIGN" => Tag::Assign,
+            "LET" => Tag::Let,
+            "FN" => Tag::Fn,
+            "IF" => Tag::If,
+            "ELSE" => Tag::Else,
+            "WHILE" => Tag::While,
+            "TRUE" => Tag::True,
+            "FALSE" => Tag::False,
+            _ => return None,
+        })
+    }
+}
+
+// TOKEN SPEC
+pub const TOKEN_SPEC: &str = r#"
+SPACE    = [ \t\r]+
+NEWLINE  = \n
+COMMENT  = #[^\n]*|/\*([^*]|\*+[^*/])*\*+/
+OPEN_COMMENT = /\*([^*]|\*+[^*/])*\**
+STRING   = "([^"\\\n]|\\.)*"
+OPEN_STRING = "([^"\\\n]|\\.)*\\?
+LET      = let
+FN       = fn
+IF       = if
+ELSE     = else
+WHILE    = while
+TRUE     = true
+FALSE    = false
+IDENT    = [\p{XID_Start}_]\p{XID_Continue}*
+NUMBER   = [0-9]+
+RADIX    = 0[xX][0-9a-fA-F]+|0[oO][0-7]+|0[bB][01]+
+FLOAT    = [0-9]+\.[0-9]+([eE][-+]?[0-9]+)?|[0-9]+[eE][-+]?[0-9]+
+OPERATOR = [-+*/]|[<>=!]=|[<>!]|&&|\|\|
+LPAREN   = \(
+RPAREN   = \)
+LBRACE   = \{
+RBRACE   = \}
+COMMA    = ,
+SEMI     = ;
+ASSIGN   = =
+"#;
+
+struct LexTable {
+    dfa: Dfa,
+    tags: Vec<Tag>,
+}
+
+fn lex_table() -> &'static LexTable {
+    static TABLE: OnceLock<LexTable> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let rules = spec::parse(TOKEN_SPEC).expect("built-in token spec is valid");
+        let tags = rules
+            .iter()
+            .map(|r| Tag::from_name(&r.name).expect("built-in rule has a tag"))
+            .collect();
+        let dfa = Dfa::from_nfa(&Nfa::from_rules(&rules)).minimize();
+        LexTable { dfa, tags }
+    })
+}
+
+// Resolves the escapes in the body of a string literal that starts at byte
+// `base`: \n \t \r \0 \" \\ and \u{hex}.
+fn unescape(body: &str, base: usize) -> Result<String, Error> {
+    let mut out = String::new();
+    let mut chars = body.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let c = match chars.next() {
+            Some((_, 'n')) => '\n',
+            Some((_, 't')) => '\t',
+            Some((_, 'r')) => '\r',
+            Some((_, '0')) => '\0',
+            Some((_, c @ ('"' | '\\'))) => c,
+            Some((_, 'u')) if chars.next_if(|&(_, c)| c == '{').is_some() => {
+                let mut hex = String::new();
+                let mut end = None;
+                for (j, c) in chars.by_ref() {
+                    if c == '}' {
+                        end = Some(j + 1);
+                        break;
+                    }
+                    hex.push(c);
+                }
+                let span = Span::new(base + i, base + end.unwrap_or(body.len()));
+                let valid = end.is_some() && (1..=6).contains(&hex.len());
+                match u32::from_str_radix(&hex, 16).ok().filter(|_| valid).and_then(char::from_u32) {
+                    Some(c) => c,
+                    None => {
+                        return Err(Error::new(Code::InvalidEscape, "invalid unicode escape", span)
+                            .with_help("write it as \\u{...} with 1 to 6 hex digits of a valid char"));
+                    }
+                }
+            }
+            other => {
+                let end = other.map_or(body.len(), |(j, c)| j + c.len_utf8());
+                let span = Span::new(base + i, base + end);
+                return Err(Error::new(Code::InvalidEscape, format!("unknown escape '{}'", &body[i..end]), span)
+                    .with_note("known escapes are \\n \\t \\r \\0 \\\" \\\\ and \\u{...}"));
+            }
+        };
+        out.push(c);
+    }
+    Ok(out)
+}
+
+fn token_kind(tag: Tag, text: &str, span: Span) -> Option<TokenKind> {
+    match tag {
+        Tag::Skip => None,
+        Tag::Comment => Some(TokenKind::Comment(text.to_string())),
+        Tag::Ident => Some(TokenKind::Ident(text.to_string())),
+        Tag::Number | Tag::RadixNumber => {
+            let (digits, radix) = match text.get(..2) {
+                Some("0x" | "0X") => (&text[2..], 16),
+                Some("0o" | "0O") => (&text[2..], 8),
+                Some("0b" | "0B") => (&text[2..], 2),
+                _ => (text, 10),
+            };
+            Some(match BigInt::parse_radix(digits, radix) {
+                Some(value) => TokenKind::Number(Value::from_big(value)),
+                None => TokenKind::Invalid(Error::new(Code::InvalidNumber, "invalid number", span)),
+            })
+        }
+        Tag::Float => Some(match text.parse::<f64>().ok().filter(|f| f.is_finite()) {
+            Some(value) => TokenKind::Number(Value::Float(value)),
+            None => TokenKind::Invalid(Error::new(Code::InvalidNumber, "float literal out of range", span)),
+        }),
+        Tag::Str => Some(match unescape(&text[1..text.len() - 1], span.start + 1) {
+            Ok(s) => TokenKind::Str(s),
+            Err(e) => TokenKind::Invalid(e),
+        }),
+        Tag::OpenStr => {
+            let err = Error::new(Code::UnterminatedString, "unterminated string", span)
+                .with_help("strings end with '\"' on the line they start on");
+            Some(TokenKind::Invalid(err))
+        }
+        Tag::OpenComment => {
+            let err = Error::new(Code::UnterminatedComment, "unterminated block comment", Span::new(span.start, span.start + 2))
+                .with_help("close it with '*/'");
+            Some(TokenKind::Invalid(err))
+        }
+        Tag::Operator => Some(TokenKind::Operator(text.to_string())),
+        Tag::LParen => Some(TokenKind::LParen),
+        Tag::RParen => Some(TokenKind::RParen),
+        Tag::LBrace => Some(TokenKind::LBrace),
+        Tag::RBrace => Some(TokenKind::RBrace),
+        Tag::Comma => Some(TokenKind::Comma),
+        Tag::Semi => Some(TokenKind::Semi),
+        Tag::Newline => Some(TokenKind::Newline),
+        Tag::Assign => Some(TokenKind::Assign),
+        Tag::Let => Some(TokenKind::Let),
+        Tag::Fn => Some(TokenKind::Fn),
+        Tag::If => Some(TokenKind::If),
+        Tag::Else => Some(TokenKind::Else),
+        Tag::While => Some(TokenKind::While),
+        Tag::True => Some(TokenKind::True),
+        Tag::False => Some(TokenKind::False),
+    }
+}
+
+// Pulls chars from any `BufRead` and yields tokens as soon as maximal munch
+// settles them, so only the current token and its lookahead are held in
+// memory. A lexer built `with_text` also keeps consumed source until
+// `drain_text` hands it out, which lets callers quote it in diagnostics.
+pub struct Lexer<R> {
+    reader: R,
+    window: VecDeque<char>,
+    offset: usize,
+    chars: usize,
+    line: usize,
+    col: usize,
+    text: Option<String>,
+    comments: bool,
+    // A read error hit while looking past a complete token; raised once
+    // that token has been returned.
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead> Lexer<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, window: VecDeque::new(), offset: 0, chars: 0, line: 1, col: 1, text: None, comments: false, error: None }
+    }
+
+    // Yields comments as `TokenKind::Comment` instead of skipping them.
+    pub fn with_comments(mut self) -> Self {
+        self.comments = true;
+        self
+    }
+
+    // Keeps consumed source for `drain_text`. Without it, iterating holds
+    // no more than the current token however long the input is.
+    pub fn with_text(mut self) -> Self {
+        self.text = Some(String::new());
+        self
+    }
+
+    // Decodes one UTF-8 char, or None at end of input.
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        let mut bytes = [0u8; 4];
+        let buf = self.reader.fill_buf()?;
+        let Some(&first) = buf.first() else { return Ok(None) };
+        let len = match first {
+            0xf0.. => 4,
+            0xe0.. => 3,
+            0xc0.. => 2,
+            _ => 1,
+        };
+        for b in bytes.iter_mut().take(len) {
+            let buf = self.reader.fill_buf()?;
+            let Some(&next) = buf.first() else { break };
+            *b = next;
+            self.reader.consume(1);
+        }
+        match std::str::from_utf8(&bytes[..len]).ok().and_then(|s| s.chars().next()) {
+            Some(c) => Ok(Some(c)),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, "input is not valid UTF-8")),
+        }
+    }
+
+    // Makes sure `window[i]` exists unless the input ends first.
+    fn fill(&mut self, i: usize) -> io::Result<bool> {
+        while self.window.len() <= i {
+            match self.read_char()? {
+                Some(c) => self.window.push_back(c),
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    // Removes `n` chars from the window, advancing the position past them.
+    fn take(&mut self, n: usize) -> (String, Span, Span, usize, usize) {
+        let (line, col) = (self.line, self.col);
+        let text: String = self.window.drain(..n).collect();
+        for c in text.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        let span = Span::new(self.offset, self.offset + text.len());
+        let char_span = Span::new(self.chars, self.chars + n);
+        self.offset = span.end;
+        self.chars = char_span.end;
+        if let Some(kept) = &mut self.text {
+            kept.push_str(&text);
+        }
+        (text, span, char_span, line, col)
+    }
+
+    fn next_token(&mut self) -> io::Result<Option<Token>> {
+        let table = lex_table();
+
+        loop {
+            if self.window.is_empty()
+                && let Some(e) = self.error.take()
+            {
+                return Err(e);
+            }
+            if !self.fill(0)? {
+                return Ok(None);
+            }
+
+            let mut state = table.dfa.start();
+            let mut last = None;
+            let mut i = 0;
+            loop {
+                match self.fill(i) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) if i > 0 => {
+                        self.error = Some(e);
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+                state = table.dfa.step(state, self.window[i]);
+                if state == DEAD {
+                    break;
+                }
+                i += 1;
+                if let Some(rule) = table.dfa.accepting(state) {
+                    last = Some((i, rule));
+                }
+            }
+
+            let Some((end, rule)) = last else {
+                let (text, span, char_span, line, col) = self.take(1);
+                let err = Error::new(Code::InvalidCharacter, format!("invalid character '{}'", text), span);
+                return Ok(Some(Token { kind: TokenKind::Invalid(err), span, char_span, line, col }));
+            };
+
+            let (text, span, char_span, line, col) = self.take(end);
+            if table.tags[rule] == Tag::Comment && !self.comments {
+                continue;
+            }
+            if let Some(kind) = token_kind(table.tags[rule], &text, span) {
+                return Ok(Some(Token { kind, span, char_span, line, col }));
+            }
+        }
+    }
+
+    // Hands out the consumed source up to byte `end`, which must not be
+    // past the last token returned.
+    pub fn drain_text(&mut self, end: usize) -> String {
+        let kept = self.text.as_mut().expect("drain_text needs a lexer built with_text");
+        let base = self.offset - kept.len();
+        let rest = kept.split_off(end - base);
+        std::mem::replace(kept, rest)
+    }
+}
+
+impl<R: BufRead> Iterator for Lexer<R> {
+    type Item = io::Result<Token>;
+
+    fn next(&mut self) -> Option<io::Result<Token>> {
+        self.next_token().transpose()
+    }
+}
+
+// Reading from a string cannot fail.
+pub fn lex(input: &str) -> Vec<Token> {
+    Lexer::new(input.as_bytes()).map(|t| t.expect("reading from memory")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(tokens: &[Token]) -> Vec<String> {
+        tokens.iter().map(|t| format!("{:?} {:?} {}:{}", t.kind, t.span, t.line, t.col)).collect()
+    }
+
+    #[test]
+    fn tiny_reads_match_whole_input() {
+        let src = "let π = 0x1f\nfn f(a, b) {\n  a <= b && é || 1.5e3 >= 2\n}\n";
+        let whole = lex(src);
+        let streamed: Vec<Token> = Lexer::new(io::BufReader::with_capacity(1, src.as_bytes()))
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(kinds(&whole), kinds(&streamed));
+    }
+
+    #[test]
+    fn tracks_lines_and_char_columns() {
+        let tokens = lex("é + 1\n  x");
+        let pos: Vec<(usize, usize)> = tokens.iter().map(|t| (t.line, t.col)).collect();
+        assert_eq!(pos, [(1, 1), (1, 3), (1, 5), (1, 6), (2, 3)]);
+    }
+
+    #[test]
+    fn unicode_identifiers_track_bytes_and_chars() {
+        let tokens = lex("naïve + 名前_2 + e\u{301}");
+        let idents: Vec<(&str, Span, Span)> = tokens
+            .iter()
+            .filter_map(|t| match &t.kind {
+                TokenKind::Ident(name) => Some((name.as_str(), t.span, t.char_span)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            idents,
+            [
+                ("naïve", Span::new(0, 6), Span::new(0, 5)),
+                ("名前_2", Span::new(9, 17), Span::new(8, 12)),
+                ("e\u{301}", Span::new(20, 23), Span::new(15, 17)),
+            ]
+        );
+        assert!(matches!(lex("_x")[0].kind, TokenKind::Ident(_)));
+        assert!(matches!(lex("\u{301}x")[0].kind, TokenKind::Invalid(_)));
+        // Alphanumeric but outside XID_Continue, so the identifier stops there.
+        for src in ["x²", "x½", "x①", "xⓐ"] {
+            let tokens = lex(src);
+            assert!(matches!(&tokens[0].kind, TokenKind::Ident(name) if name == "x"), "{}", src);
+            assert!(matches!(&tokens[1].kind, TokenKind::Invalid(e) if e.code == Code::InvalidCharacter), "{}", src);
+        }
+    }
+
+    #[test]
+    fn skips_comments_and_unescapes_strings() {
+        let tokens = lex("1 # note\n/* a\n * b */ \"x\\ty\\\"\\u{e9}\\\\\"");
+        let kinds: Vec<String> = tokens.iter().map(|t| format!("{:?}", t.kind)).collect();
+        assert_eq!(kinds, ["Number(Int(1))", "Newline", "Str(\"x\\ty\\\"é\\\\\")"]);
+        assert_eq!((tokens[2].line, tokens[2].col), (3, 9));
+
+        let src = "1 # note\n/* a */";
+        let comments: Vec<Span> = Lexer::new(src.as_bytes())
+            .with_comments()
+            .map(Result::unwrap)
+            .filter(|t| matches!(t.kind, TokenKind::Comment(_)))
+            .map(|t| t.span)
+            .collect();
+        assert_eq!(comments, [Span::new(2, 8), Span::new(9, 16)]);
+    }
+
+    #[test]
+    fn reports_bad_strings_and_comments() {
+        let code = |src: &str| match &lex(src).last().unwrap().kind {
+            TokenKind::Invalid(e) => Some((e.code, e.span)),
+            _ => None,
+        };
+        assert_eq!(code(r#""a\qb""#), Some((Code::InvalidEscape, Span::new(2, 4))));
+        assert_eq!(code(r#""\u{110000}""#), Some((Code::InvalidEscape, Span::new(1, 11))));
+        assert_eq!(code("\"open"), Some((Code::UnterminatedString, Span::new(0, 5))));
+        assert_eq!(code("1 /* open *"), Some((Code::UnterminatedComment, Span::new(2, 4))));
+    }
+
+    #[test]
+    fn bad_utf8_is_reported_after_the_tokens_before_it() {
+        let mut lexer = Lexer::new(&b"1\n\xff"[..]);
+        assert!(matches!(lexer.next(), Some(Ok(Token { kind: TokenKind::Number(_), .. }))));
+        assert!(matches!(lexer.next(), Some(Ok(Token { kind: TokenKind::Newline, .. }))));
+        assert!(matches!(lexer.next(), Some(Err(_))));
+    }
+
+    #[test]
+    fn keeps_source_only_when_asked() {
+        let src = "let x = 1\n".repeat(10000);
+        let mut plain = Lexer::new(src.as_bytes());
+        assert_eq!(plain.by_ref().count(), 50000);
+        assert!(plain.text.is_none());
+
+        let mut keeping = Lexer::new(src.as_bytes()).with_text();
+        let mut kept = 0;
+        while let Some(tok) = keeping.next() {
+            let tok = tok.unwrap();
+            if matches!(tok.kind, TokenKind::Newline) {
+                assert_eq!(keeping.drain_text(tok.span.end), "let x = 1\n");
+                kept = kept.max(keeping.text.as_ref().unwrap().len());
+            }
+        }
+        assert_eq!(kept, 0);
+    }
+}
@@ -0,0 +1,124 @@
+mod bigint;
+pub mod codegen;
+pub mod dot;
+pub mod fmt;
+pub mod diag;
+mod diff;
+mod eval;
+mod lexer;
+pub mod lsp;
+mod optimize;
+mod parser;
+mod rational;
+mod regex;
+mod value;
+mod vm;
+
+// Internals that the binary and the integration tests reach into; not part
+// of the library's API.
+#[doc(hidden)]
+pub mod dfa;
+#[doc(hidden)]
+pub mod json;
+#[doc(hidden)]
+pub mod nfa;
+#[doc(hidden)]
+pub mod spec;
+#[doc(hidden)]
+pub mod unicode;
+
+pub use bigint::BigInt;
+pub use diag::{Code, Error, Span};
+pub use diff::diff;
+pub use eval::{Backend, Env, eval, execute};
+pub use lexer::{Lexer, TOKEN_SPEC, Token, TokenKind};
+pub use optimize::optimize;
+pub use parser::{Expr, Parser, Statement, Statements};
+pub use rational::Rational;
+pub use value::{Mode, Numeric, Value};
+
+// Lexes a whole string, failing on the first invalid token. Use `Lexer`
+// directly to keep going past bad input or to read from a stream.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+    let tokens = lexer::lex(input);
+    match tokens.iter().find_map(|t| match &t.kind {
+        TokenKind::Invalid(e) => Some(e.clone()),
+        _ => None,
+    }) {
+        Some(e) => Err(e),
+        None => Ok(tokens),
+    }
+}
+
+// Owns the environment that bindings and functions live in between calls.
+#[derive(Debug, Default)]
+pub struct Interpreter {
+    env: Env,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_mode(mut self, mode: Mode) -> Self {
+        self.env.mode = mode;
+        self
+    }
+
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.env.backend = backend;
+        self
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.env.mode
+    }
+
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+
+    // Runs every statement in `src` and returns the value of the last one,
+    // which is `Value::Unit` for `let`, `fn` and loops. Stops at the first
+    // error; spans in it refer to `src`.
+    pub fn eval(&mut self, src: &str) -> Result<Value, Error> {
+        let mut parser = Parser::new(lexer::lex(src));
+        let mut last = Value::Unit;
+        loop {
+            parser.skip_separators();
+            if parser.at_end() {
+                return Ok(last);
+            }
+            let stmt = parser.parse_statement()?;
+            let v = self.execute(&stmt)?;
+            last = if stmt.is_statement() { Value::Unit } else { v };
+        }
+    }
+
+    pub fn execute(&mut self, stmt: &Expr) -> Result<Value, Error> {
+        execute(stmt, &mut self.env)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.env.scopes[0].get(name).cloned()
+    }
+
+    // Global bindings, sorted by name.
+    pub fn globals(&self) -> Vec<(&str, &Value)> {
+        let mut vars: Vec<_> = self.env.scopes[0].iter().map(|(k, v)| (k.as_str(), v)).collect();
+        vars.sort_by_key(|v| v.0);
+        vars
+    }
+
+    // User-defined functions and their parameters, sorted by name.
+    pub fn functions(&self) -> Vec<(&str, &[String])> {
+        let mut fns: Vec<_> = self.env.functions.iter().map(|(k, f)| (k.as_str(), f.params.as_slice())).collect();
+        fns.sort_by_key(|f| f.0);
+        fns
+    }
+
+    pub fn reset(&mut self) {
+        self.env.reset();
+    }
+}
@@ -0,0 +1,641 @@
+// A language server speaking LSP over stdin/stdout. Documents are analysed,
+// never run, so hover shows a value only where constant folding can work it
+// out and `print` never writes into the protocol stream.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::diag::{Error, Span};
+use crate::eval::{builtin, builtins};
+use crate::fmt;
+use crate::json::{self, Json};
+use crate::lexer::{Token, TokenKind, lex};
+use crate::optimize::{from_value, optimize};
+use crate::parser::{Expr, Parser};
+use crate::value::{Mode, Value};
+
+const KEYWORDS: &[&str] = &["let", "fn", "if", "else", "while", "true", "false"];
+
+// LSP positions count UTF-16 code units within a line.
+fn position(text: &str, offset: usize) -> Json {
+    let offset = offset.min(text.len());
+    let start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = text[..start].matches('\n').count();
+    Json::object([("line", line.into()), ("character", text[start..offset].encode_utf16().count().into())])
+}
+
+fn range(text: &str, span: Span) -> Json {
+    Json::object([("start", position(text, span.start)), ("end", position(text, span.end))])
+}
+
+// The byte offset of an LSP position, clamped to the end of its line.
+fn offset(text: &str, pos: &Json) -> Option<usize> {
+    let line = pos.get(&["line"])?.as_usize()?;
+    let character = pos.get(&["character"])?.as_usize()?;
+    let start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+// Lexer, parser and optimizer errors, in source order. The parser reports
+// invalid tokens again when it reaches them; those are kept once.
+fn diagnostics(text: &str) -> Vec<Error> {
+    let tokens = lex(text);
+    let mut errors: Vec<Error> = tokens
+        .iter()
+        .filter_map(|t| match &t.kind {
+            TokenKind::Invalid(e) => Some(e.clone()),
+            _ => None,
+        })
+        .collect();
+    let (stmts, parse_errors) = Parser::new(tokens).parse_program();
+    for e in parse_errors {
+        if !errors.iter().any(|x| x.code == e.code && x.span == e.span) {
+            errors.push(e);
+        }
+    }
+    errors.extend(stmts.iter().filter_map(|s| optimize(s, Mode::default()).err()));
+    errors.sort_by_key(|e| e.span.start);
+    errors
+}
+
+fn diagnostic(uri: &str, text: &str, err: &Error) -> Json {
+    let mut message = err.msg.clone();
+    for note in &err.notes {
+        message.push_str("\nnote: ");
+        message.push_str(note);
+    }
+    if let Some(help) = &err.help {
+        message.push_str("\nhelp: ");
+        message.push_str(help);
+    }
+    let related = err.labels.iter().map(|(span, label)| {
+        let location = Json::object([("uri", uri.into()), ("range", range(text, *span))]);
+        Json::object([("location", location), ("message", label.as_str().into())])
+    });
+    Json::object([
+        ("range", range(text, err.span)),
+        ("severity", 1.into()),
+        ("code", err.code.to_string().into()),
+        ("source", "dfa-lexer".into()),
+        ("message", message.into()),
+        ("relatedInformation", Json::Array(related.collect())),
+    ])
+}
+
+#[derive(Debug)]
+enum Binding {
+    Let(Expr),
+    // The function the parameter belongs to.
+    Param(String),
+    Fn(Vec<String>),
+}
+
+#[derive(Debug)]
+struct Def {
+    name: String,
+    // The name where it is bound, and the range where it can be used.
+    span: Span,
+    visible: Span,
+    binding: Binding,
+    // Assigned after the `let`, so not a constant.
+    assigned: bool,
+}
+
+// Where each name in a document is bound and what each use refers to.
+struct Analysis {
+    tokens: Vec<Token>,
+    stmts: Vec<Expr>,
+    defs: Vec<Def>,
+    uses: Vec<(Span, usize)>,
+}
+
+fn end_of(e: &Expr) -> usize {
+    match e {
+        Expr::Number { span, .. } | Expr::Ident { span, .. } | Expr::Error { span } | Expr::Block { span, .. } => span.end,
+        Expr::Bool(_) | Expr::Str(_) => 0,
+        Expr::Let { value, span, .. } | Expr::Assign { value, span, .. } => span.end.max(end_of(value)),
+        Expr::FnDef { body, span, .. } => span.end.max(end_of(body)),
+        Expr::Call { args, span, .. } => args.iter().map(end_of).fold(span.end, usize::max),
+        Expr::Unary { operand, span, .. } => span.end.max(end_of(operand)),
+        Expr::Binary { .. } => {
+            let (first, rest) = e.chain();
+            rest.iter().map(|r| end_of(r.2)).fold(end_of(first), usize::max)
+        }
+        Expr::If { cond, then, otherwise, .. } => end_of(cond).max(end_of(then)).max(otherwise.as_deref().map_or(0, end_of)),
+        Expr::While { cond, body, .. } => end_of(cond).max(end_of(body)),
+    }
+}
+
+// Scopes follow `Env`: blocks nest, and a function body sees its own
+// scopes and the globals but not the blocks around its definition.
+struct Walker<'a> {
+    tokens: &'a [Token],
+    defs: Vec<Def>,
+    uses: Vec<(Span, usize)>,
+    scopes: Vec<(Vec<usize>, usize)>,
+    frame: usize,
+    // Uses in function bodies of globals that may be bound later.
+    pending: Vec<(String, Span)>,
+}
+
+impl Walker<'_> {
+    fn define(&mut self, name: &str, span: Span, binding: Binding) -> usize {
+        let (defs, end) = self.scopes.last_mut().unwrap();
+        let visible = Span::new(span.end, *end);
+        self.defs.push(Def { name: name.to_string(), span, visible, binding, assigned: false });
+        defs.push(self.defs.len() - 1);
+        self.defs.len() - 1
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        let globals = if self.frame > 0 { &self.scopes[..1] } else { &[] };
+        self.scopes[self.frame..].iter().rev().chain(globals).flat_map(|s| s.0.iter().rev()).copied().find(|&d| self.defs[d].name == name)
+    }
+
+    fn use_var(&mut self, name: &str, span: Span) -> Option<usize> {
+        match self.lookup(name) {
+            Some(d) => self.uses.push((span, d)),
+            None if self.frame > 0 => self.pending.push((name.to_string(), span)),
+            None => {}
+        }
+        self.lookup(name)
+    }
+
+    // The latest definition of a function before `span`, or else the first.
+    fn use_fn(&mut self, name: &str, span: Span) {
+        let fns = (0..self.defs.len()).filter(|&d| matches!(self.defs[d].binding, Binding::Fn(_)) && self.defs[d].name == name);
+        if let Some(d) = fns.clone().rfind(|&d| self.defs[d].span.start < span.start).or(fns.min()) {
+            self.uses.push((span, d));
+        }
+    }
+
+    // The parameter names follow the function name in the source.
+    fn param_spans(&self, name: Span) -> Vec<Span> {
+        let at = self.tokens.iter().position(|t| t.span == name).map_or(self.tokens.len(), |i| i + 1);
+        self.tokens[at..]
+            .iter()
+            .take_while(|t| !matches!(t.kind, TokenKind::RParen))
+            .filter(|t| matches!(t.kind, TokenKind::Ident(_)))
+            .map(|t| t.span)
+            .collect()
+    }
+
+    fn declare_fns(&mut self, e: &Expr) {
+        match e {
+            Expr::FnDef { name, params, body, span } => {
+                self.define(name, *span, Binding::Fn(params.clone()));
+                self.declare_fns(body);
+            }
+            Expr::Block { stmts, .. } => stmts.iter().for_each(|s| self.declare_fns(s)),
+            Expr::If { then, otherwise, .. } => {
+                self.declare_fns(then);
+                otherwise.iter().for_each(|o| self.declare_fns(o));
+            }
+            Expr::While { body, .. } => self.declare_fns(body),
+            _ => {}
+        }
+    }
+
+    fn walk(&mut self, e: &Expr) {
+        match e {
+            Expr::Ident { name, span } => {
+                self.use_var(name, *span);
+            }
+            Expr::Let { name, value, span } => {
+                self.walk(value);
+                self.define(name, *span, Binding::Let((**value).clone()));
+            }
+            Expr::Assign { name, value, span } => {
+                self.walk(value);
+                match self.use_var(name, *span) {
+                    Some(d) => self.defs[d].assigned = true,
+                    None => {
+                        self.define(name, *span, Binding::Let((**value).clone()));
+                    }
+                }
+            }
+            Expr::FnDef { name, params, body, span } => {
+                let outer = self.frame;
+                self.frame = self.scopes.len();
+                self.scopes.push((Vec::new(), end_of(body)));
+                for (param, at) in params.iter().zip(self.param_spans(*span)) {
+                    self.define(param, at, Binding::Param(name.clone()));
+                }
+                self.walk(body);
+                self.scopes.pop();
+                self.frame = outer;
+            }
+            Expr::Call { name, args, span } => {
+                self.use_fn(name, *span);
+                args.iter().for_each(|a| self.walk(a));
+            }
+            Expr::Unary { operand, .. } => self.walk(operand),
+            Expr::Binary { .. } => {
+                let (first, rest) = e.chain();
+                self.walk(first);
+                rest.iter().for_each(|r| self.walk(r.2));
+            }
+            Expr::Block { stmts, span } => {
+                self.scopes.push((Vec::new(), span.end));
+                stmts.iter().for_each(|s| self.walk(s));
+                self.scopes.pop();
+            }
+            Expr::If { cond, then, otherwise, .. } => {
+                self.walk(cond);
+                self.walk(then);
+                otherwise.iter().for_each(|o| self.walk(o));
+            }
+            Expr::While { cond, body, .. } => {
+                self.walk(cond);
+                self.walk(body);
+            }
+            Expr::Number { .. } | Expr::Bool(_) | Expr::Str(_) | Expr::Error { .. } => {}
+        }
+    }
+}
+
+impl Analysis {
+    fn new(text: &str) -> Analysis {
+        let tokens = lex(text);
+        let (stmts, _) = Parser::new(tokens.clone()).parse_program();
+        let mut w = Walker { tokens: &tokens, defs: Vec::new(), uses: Vec::new(), scopes: vec![(Vec::new(), text.len())], frame: 0, pending: Vec::new() };
+        stmts.iter().for_each(|s| w.declare_fns(s));
+        // Functions are visible everywhere, whatever scope defined them.
+        w.scopes[0].0.clear();
+        w.defs.iter_mut().for_each(|d| d.visible = Span::new(0, text.len()));
+        stmts.iter().for_each(|s| w.walk(s));
+        for (name, span) in std::mem::take(&mut w.pending) {
+            if let Some(&d) = w.scopes[0].0.iter().find(|&&d| w.defs[d].name == name) {
+                w.uses.push((span, d));
+            }
+        }
+        let (defs, uses) = (w.defs, w.uses);
+        Analysis { tokens, stmts, defs, uses }
+    }
+
+    // What the name at `span` refers to, at a use or where it is bound.
+    fn target(&self, span: Span) -> Option<usize> {
+        self.uses.iter().find(|u| u.0 == span).map(|u| u.1).or_else(|| self.defs.iter().position(|d| d.span == span))
+    }
+
+    fn ident_at(&self, offset: usize) -> Option<(&str, Span)> {
+        self.tokens.iter().find_map(|t| match &t.kind {
+            TokenKind::Ident(name) if t.span.start <= offset && offset <= t.span.end => Some((name.as_str(), t.span)),
+            _ => None,
+        })
+    }
+
+    // The value of a `let` that is never reassigned, if it folds.
+    fn value(&self, d: usize, seen: &mut Vec<usize>) -> Option<Value> {
+        match &self.defs[d].binding {
+            Binding::Let(e) if !self.defs[d].assigned && !seen.contains(&d) => {
+                seen.push(d);
+                let v = self.fold(e, seen);
+                seen.pop();
+                v
+            }
+            _ => None,
+        }
+    }
+
+    fn fold(&self, e: &Expr, seen: &mut Vec<usize>) -> Option<Value> {
+        match optimize(&self.substitute(e, seen), Mode::default()).ok()? {
+            Expr::Number { value, .. } => Some(value),
+            Expr::Bool(b) => Some(Value::Bool(b)),
+            Expr::Str(s) => Some(Value::Str(s)),
+            _ => None,
+        }
+    }
+
+    // `e` with the constant bindings it uses replaced by their values.
+    fn substitute(&self, e: &Expr, seen: &mut Vec<usize>) -> Expr {
+        let mut sub = |e: &Expr| Box::new(self.substitute(e, seen));
+        match e {
+            Expr::Ident { span, .. } => match self.target(*span).and_then(|d| self.value(d, seen)) {
+                Some(v) => from_value(v, *span),
+                None => e.clone(),
+            },
+            Expr::Unary { op, span, operand } => Expr::Unary { op: op.clone(), span: *span, operand: sub(operand) },
+            Expr::Binary { .. } => {
+                let (first, rest) = e.chain();
+                let mut out = *sub(first);
+                for (op, span, right) in rest {
+                    out = Expr::Binary { op: op.to_string(), span, left: Box::new(out), right: sub(right) };
+                }
+                out
+            }
+            Expr::If { cond, then, otherwise, span } => {
+                let (cond, then) = (sub(cond), sub(then));
+                Expr::If { cond, then, otherwise: otherwise.as_deref().map(sub), span: *span }
+            }
+            Expr::Call { name, args, span } => Expr::Call { name: name.clone(), args: args.iter().map(|a| *sub(a)).collect(), span: *span },
+            _ => e.clone(),
+        }
+    }
+
+    fn describe(&self, d: usize) -> String {
+        let def = &self.defs[d];
+        match &def.binding {
+            Binding::Let(_) => match self.value(d, &mut Vec::new()) {
+                Some(v) => format!("let {} = {}", def.name, fmt::expr(&from_value(v, Span::default()))),
+                None => format!("let {}", def.name),
+            },
+            Binding::Param(f) => format!("{}: parameter of {}", def.name, f),
+            Binding::Fn(params) => format!("fn {}({})", def.name, params.join(", ")),
+        }
+    }
+
+    // The innermost operator at `offset` whose expression folds.
+    fn operator_at(&self, offset: usize) -> Option<(Span, Value)> {
+        fn find<'e>(e: &'e Expr, offset: usize, found: &mut Option<&'e Expr>) {
+            // The operators of a run, outermost first, then its operands.
+            if let Expr::Binary { .. } = e {
+                let mut node = e;
+                while let Expr::Binary { span, left, .. } = node {
+                    if span.start <= offset && offset < span.end {
+                        *found = Some(node);
+                    }
+                    node = left;
+                }
+                let (first, rest) = e.chain();
+                find(first, offset, found);
+                rest.iter().for_each(|r| find(r.2, offset, found));
+                return;
+            }
+            if let Expr::Unary { span, .. } = e
+                && span.start <= offset
+                && offset < span.end
+            {
+                *found = Some(e);
+            }
+            match e {
+                Expr::Let { value, .. } | Expr::Assign { value, .. } => find(value, offset, found),
+                Expr::FnDef { body, .. } => find(body, offset, found),
+                Expr::Call { args, .. } => args.iter().for_each(|a| find(a, offset, found)),
+                Expr::Unary { operand, .. } => find(operand, offset, found),
+                Expr::Block { stmts, .. } => stmts.iter().for_each(|s| find(s, offset, found)),
+                Expr::If { cond, then, otherwise, .. } => {
+                    find(cond, offset, found);
+                    find(then, offset, found);
+                    otherwise.iter().for_each(|o| find(o, offset, found));
+                }
+                Expr::While { cond, body, .. } => {
+                    find(cond, offset, found);
+                    find(body, offset, found);
+                }
+                _ => {}
+            }
+        }
+        let mut found = None;
+        self.stmts.iter().for_each(|s| find(s, offset, &mut found));
+        match found? {
+            e @ (Expr::Unary { span, .. } | Expr::Binary { span, .. }) => Some((*span, self.fold(e, &mut Vec::new())?)),
+            _ => None,
+        }
+    }
+
+    fn hover(&self, text: &str, offset: usize) -> Json {
+        let (contents, span) = match self.ident_at(offset) {
+            Some((name, span)) => match (self.target(span), builtin(name)) {
+                (Some(d), _) => (self.describe(d), span),
+                (None, Some((Some(arity), _))) => (format!("builtin {}, {} arguments", name, arity), span),
+                (None, Some((None, _))) => (format!("builtin {}, any number of arguments", name), span),
+                (None, None) => return Json::Null,
+            },
+            None => match self.operator_at(offset) {
+                Some((span, v)) => (format!("= {}", fmt::expr(&from_value(v, Span::default()))), span),
+                None => return Json::Null,
+            },
+        };
+        let contents = Json::object([("kind", "plaintext".into()), ("value", contents.into())]);
+        Json::object([("contents", contents), ("range", range(text, span))])
+    }
+
+    fn definition(&self, uri: &str, text: &str, offset: usize) -> Json {
+        match self.ident_at(offset).and_then(|(_, span)| self.target(span)) {
+            Some(d) => Json::object([("uri", uri.into()), ("range", range(text, self.defs[d].span))]),
+            None => Json::Null,
+        }
+    }
+
+    // Bindings visible at `offset`, innermost first, then functions,
+    // builtins and keywords.
+    fn completion(&self, offset: usize) -> Json {
+        let mut items: Vec<(String, usize, String)> = Vec::new();
+        let mut visible: Vec<usize> = (0..self.defs.len()).filter(|&d| self.defs[d].visible.start <= offset && offset <= self.defs[d].visible.end).collect();
+        visible.sort_by_key(|&d| (matches!(self.defs[d].binding, Binding::Fn(_)), std::cmp::Reverse(self.defs[d].span.start)));
+        for d in visible {
+            let kind = if matches!(self.defs[d].binding, Binding::Fn(_)) { 3 } else { 6 };
+            if !items.iter().any(|i| i.0 == self.defs[d].name && i.1 == kind) {
+                items.push((self.defs[d].name.clone(), kind, self.describe(d)));
+            }
+        }
+        for (name, arity) in builtins() {
+            items.push((name.to_string(), 3, arity.map_or("builtin, any number of arguments".to_string(), |n| format!("builtin, {} arguments", n))));
+        }
+        items.extend(KEYWORDS.iter().map(|k| (k.to_string(), 14, "keyword".to_string())));
+        Json::Array(
+            items.into_iter().map(|(label, kind, detail)| Json::object([("label", label.into()), ("kind", kind.into()), ("detail", detail.into())])).collect(),
+        )
+    }
+}
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+struct Server<W> {
+    out: W,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+// Reads one message body, or None at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "message without a Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, msg: Json) -> io::Result<()> {
+        let body = msg.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn reply(&mut self, id: Json, result: Result<Json, (i64, String)>) -> io::Result<()> {
+        let outcome = match result {
+            Ok(result) => ("result", result),
+            Err((code, message)) => ("error", Json::object([("code", Json::Number(code as f64)), ("message", message.into())])),
+        };
+        self.send(Json::object([("jsonrpc", "2.0".into()), ("id", id), outcome]))
+    }
+
+    fn publish(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(doc) => diagnostics(&doc.text).iter().map(|e| diagnostic(uri, &doc.text, e)).collect(),
+            None => Vec::new(),
+        };
+        let params = Json::object([("uri", uri.into()), ("diagnostics", Json::Array(diagnostics))]);
+        self.send(Json::object([("jsonrpc", "2.0".into()), ("method", "textDocument/publishDiagnostics".into()), ("params", params)]))
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) -> io::Result<()> {
+        let Some(uri) = params.get(&["textDocument", "uri"]).and_then(Json::as_str) else {
+            return Ok(());
+        };
+        let uri = uri.to_string();
+        // Only full-text sync is offered, so the last change holds the text.
+        let text = match method {
+            "textDocument/didOpen" => params.get(&["textDocument", "text"]),
+            "textDocument/didChange" => params.get(&["contentChanges"]).and_then(Json::as_array).and_then(|c| c.last()?.get(&["text"])),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return self.publish(&uri);
+            }
+            _ => return Ok(()),
+        };
+        if let Some(text) = text.and_then(Json::as_str) {
+            let analysis = Analysis::new(text);
+            self.documents.insert(uri.clone(), Document { text: text.to_string(), analysis });
+            self.publish(&uri)?;
+        }
+        Ok(())
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        if self.shutdown {
+            return Err((-32600, "the server is shutting down".to_string()));
+        }
+        let at = || {
+            let doc = self.documents.get(params.get(&["textDocument", "uri"])?.as_str()?)?;
+            Some((doc, offset(&doc.text, params.get(&["position"])?)?))
+        };
+        Ok(match method {
+            "initialize" => {
+                let capabilities = Json::object([
+                    ("textDocumentSync", 1.into()),
+                    ("hoverProvider", true.into()),
+                    ("completionProvider", Json::object([])),
+                    ("definitionProvider", true.into()),
+                ]);
+                let info = Json::object([("name", "dfa-lexer".into()), ("version", env!("CARGO_PKG_VERSION").into())]);
+                Json::object([("capabilities", capabilities), ("serverInfo", info)])
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "textDocument/hover" => at().map_or(Json::Null, |(doc, offset)| doc.analysis.hover(&doc.text, offset)),
+            "textDocument/completion" => at().map_or(Json::Null, |(doc, offset)| doc.analysis.completion(offset)),
+            "textDocument/definition" => {
+                let uri = params.get(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or_default();
+                at().map_or(Json::Null, |(doc, offset)| doc.analysis.definition(uri, &doc.text, offset))
+            }
+            _ => return Err((-32601, format!("unknown method '{}'", method))),
+        })
+    }
+}
+
+// Serves requests until `exit` or the end of the input. Returns whether
+// `shutdown` came first, which decides the exit status.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<bool> {
+    let mut server = Server { out: output, documents: HashMap::new(), shutdown: false };
+    while let Some(body) = read_message(&mut input)? {
+        let msg = match json::parse(&body) {
+            Ok(msg) => msg,
+            Err(e) => {
+                server.reply(Json::Null, Err((-32700, e)))?;
+                continue;
+            }
+        };
+        let method = msg.get(&["method"]).and_then(Json::as_str).unwrap_or_default();
+        let params = msg.get(&["params"]).unwrap_or(&Json::Null);
+        match msg.get(&["id"]) {
+            _ if method == "exit" => return Ok(server.shutdown),
+            Some(id) => {
+                let result = server.request(method, params);
+                server.reply(id.clone(), result)?;
+            }
+            None => server.notify(method, params)?,
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str, needle: &str) -> usize {
+        text.find(needle).unwrap()
+    }
+
+    #[test]
+    fn positions_count_utf16_units() {
+        let text = "let s = \"😀é\"\nlet x = 1";
+        let pos = position(text, at(text, "\"\n") + 1);
+        assert_eq!(pos.to_string(), r#"{"line":0,"character":13}"#);
+        assert_eq!(offset(text, &pos), Some(at(text, "\n")));
+        assert_eq!(offset(text, &json::parse(r#"{"line":1,"character":4}"#).unwrap()), Some(at(text, "x")));
+        assert_eq!(offset(text, &json::parse(r#"{"line":0,"character":99}"#).unwrap()), Some(at(text, "\n")));
+    }
+
+    #[test]
+    fn resolves_scopes_and_folds_constants() {
+        let text = "let a = 2\nlet b = a * 3\nlet c = 1\nc = 5\nfn f(a) { let b = a\n b + g }\nlet g = b - 1";
+        let a = Analysis::new(text);
+        let hover = |needle: &str, skip: usize| a.hover(text, at(text, needle) + skip).get(&["contents", "value"]).cloned();
+        assert_eq!(hover("b =", 0), Some("let b = 6".into()));
+        assert_eq!(hover("c =", 0), Some("let c".into()));
+        assert_eq!(hover("* 3", 0), Some("= 6".into()));
+        assert_eq!(hover("b + g", 0), Some("let b".into()));
+        assert_eq!(hover("g }", 0), Some("let g = 5".into()));
+        assert_eq!(hover("a\n b", 0), Some("a: parameter of f".into()));
+        assert_eq!(hover("= 1\n", 0), None);
+
+        let def = |needle: &str| a.definition("u", text, at(text, needle)).get(&["range", "start"]).map(Json::to_string);
+        assert_eq!(def("a\n b"), Some(r#"{"line":4,"character":5}"#.to_string()));
+        assert_eq!(def("g }"), Some(r#"{"line":6,"character":4}"#.to_string()));
+
+        let labels = a.completion(at(text, "b + g")).to_string();
+        assert!(labels.contains(r#""label":"a","kind":6,"detail":"a: parameter of f""#), "{}", labels);
+        assert!(labels.contains(r#""label":"f""#) && labels.contains(r#""label":"pow""#));
+        assert!(labels.contains(r#""label":"b","kind":6,"detail":"let b""#) && !labels.contains("let b = 6"));
+    }
+
+    #[test]
+    fn reports_each_error_once() {
+        let errors = diagnostics("let x = @\nlet y = 1 / 0\nz = (");
+        let codes: Vec<String> = errors.iter().map(|e| e.code.to_string()).collect();
+        assert_eq!(codes, ["E0001", "E0104", "E0003"]);
+    }
+}
@@ -1,7 +1,64 @@
-use std::collections::HashMap;
+mod term;
+
 use std::env;
 use std::fs;
-use std::io::{self, Read, Write};
+use std::io::{self, BufRead, BufReader, Read};
+use std::process;
+use std::time::Instant;
+
+use dfa_lexer::dfa::Dfa;
+use dfa_lexer::nfa::Nfa;
+use dfa_lexer::{Backend, Code, Error, Expr, Interpreter, Lexer, Mode, Numeric, Parser, Statement, Statements, TOKEN_SPEC, TokenKind, Value, codegen, diag, diff, dot, fmt, lsp, optimize, spec};
+
+#[cfg(unix)]
+mod libc {
+    use std::ffi::c_int;
+
+    pub const STDIN_FILENO: c_int = 0;
+
+    unsafe extern "C" {
+        pub fn isatty(fd: c_int) -> c_int;
+    }
+
+    // glibc/musl layout and flag values on x86_64 and aarch64 Linux. Other
+    // architectures (mips, powerpc, sparc) number the flags and lay out
+    // termios differently, so they get no raw mode.
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub use self::linux::*;
+
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    mod linux {
+        use std::ffi::c_int;
+
+        pub const ICRNL: u32 = 0o400;
+        pub const IXON: u32 = 0o2000;
+        pub const ISIG: u32 = 0o1;
+        pub const ICANON: u32 = 0o2;
+        pub const ECHO: u32 = 0o10;
+        pub const IEXTEN: u32 = 0o100000;
+        pub const VTIME: usize = 5;
+        pub const VMIN: usize = 6;
+        pub const TCSADRAIN: c_int = 1;
+
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct termios {
+            pub c_iflag: u32,
+            pub c_oflag: u32,
+            pub c_cflag: u32,
+            pub c_lflag: u32,
+            pub c_line: u8,
+            pub c_cc: [u8; 32],
+            pub c_ispeed: u32,
+            pub c_ospeed: u32,
+        }
+
+        unsafe extern "C" {
+            pub fn tcgetattr(fd: c_int, termios: *mut termios) -> c_int;
+            pub fn tcsetattr(fd: c_int, action: c_int, termios: *const termios) -> c_int;
+        }
+    }
+}
 
 #[cfg(unix)]
 fn stdin_is_tty() -> bool {
@@ -13,272 +70,576 @@ fn stdin_is_tty() -> bool {
     false
 }
 
-#[derive(Debug)]
-struct Error {
-    msg: String,
-    col: usize,
-}
-
-impl Error {
-    fn new(msg: &str, col: usize) -> Self {
-        Self { msg: msg.into(), col }
-    }
-}
-
-#[derive(Debug)]
-enum TokenKind {
-    Ident(String),
-    Number(i64),
-    Operator(String),
-    LParen,
-    RParen,
-    Comma,
+fn dump_stats() {
+    let rules = spec::parse(TOKEN_SPEC).expect("built-in token spec is valid");
+    let nfa = Nfa::from_rules(&rules);
+    let dfa = Dfa::from_nfa(&nfa);
+    let min = dfa.minimize();
+
+    println!("rules:            {}", rules.len());
+    println!("alphabet classes: {}", dfa.classes());
+    println!("nfa states:       {}", nfa.states.len());
+    println!("dfa states:       {}", dfa.states());
+    println!("minimized states: {}", min.states());
 }
 
-#[derive(Debug)]
-struct Token {
-    kind: TokenKind,
-    col: usize,
+fn render_error(source: &str, first_line: usize, err: Error) {
+    eprint!("{}", diag::render(source, first_line, &err));
 }
 
-fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
-    let chars: Vec<char> = input.chars().collect();
-    let mut i = 0;
-    let mut tokens = Vec::new();
-
-    while i < chars.len() {
-        match chars[i] {
-            ' ' | '\t' | '\r' => i += 1,
+// Runs the input one top-level statement at a time as it is read, so piped
+// or generated scripts of any length need only constant memory. Returns the
+// number of statements that failed. Without `keep_going` the script stops at
+// the first one. `first_line` numbers the input's first line in diagnostics.
+fn run_script(input: impl BufRead, first_line: usize, interp: &mut Interpreter, keep_going: bool) -> usize {
+    let mut statements = Statements::new(input, first_line);
+    let mut failed = 0;
+    // Without --keep-going the first failure stops execution, but the rest
+    // of the input is still parsed so every syntax error gets reported.
+    let mut running = true;
 
-            ',' => {
-                tokens.push(Token { kind: TokenKind::Comma, col: i });
-                i += 1;
+    loop {
+        let Statement { tokens, text, line } = match statements.next_statement() {
+            Ok(Some(next)) => next,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("input error: {}", e);
+                failed += 1;
+                break;
             }
+        };
 
-            'a'..='z' | 'A'..='Z' | '_' => {
-                let start = i;
-                while i < chars.len()
-                    && matches!(chars[i], 'a'..='z' | 'A'..='Z' | '_' | '0'..='9')
-                {
-                    i += 1;
-                }
-                let text: String = chars[start..i].iter().collect();
-                tokens.push(Token { kind: TokenKind::Ident(text), col: start });
+        let (stmts, errors) = Parser::new(tokens).parse_program();
+        if !errors.is_empty() {
+            for e in errors {
+                render_error(&text, line, e);
             }
+            failed += 1;
+            running = keep_going;
+            continue;
+        }
+        if !running {
+            continue;
+        }
 
-            '0'..='9' => {
-                let start = i;
-                while i < chars.len() && matches!(chars[i], '0'..='9') {
-                    i += 1;
+        for stmt in &stmts {
+            match interp.execute(stmt) {
+                Ok(_) if stmt.is_statement() => {}
+                Ok(Value::Unit) => {}
+                Ok(v) => println!("{}", v),
+                Err(e) => {
+                    render_error(&text, line, e);
+                    failed += 1;
+                    running = keep_going;
+                    break;
                 }
-                let text: String = chars[start..i].iter().collect();
-                let value = text.parse::<i64>().map_err(|_| Error::new("invalid number", start))?;
-                tokens.push(Token { kind: TokenKind::Number(value), col: start });
             }
-
-            '(' => { tokens.push(Token { kind: TokenKind::LParen, col: i }); i += 1; }
-            ')' => { tokens.push(Token { kind: TokenKind::RParen, col: i }); i += 1; }
-
-            '+' | '-' | '*' | '/' => {
-                tokens.push(Token {
-                    kind: TokenKind::Operator(chars[i].to_string()),
-                    col: i,
-                });
-                i += 1;
-            }
-
-            _ => return Err(Error::new(&format!("invalid character '{}'", chars[i]), i)),
         }
     }
 
-    Ok(tokens)
+    if failed > 1 {
+        eprintln!("{} statements failed", failed);
+    }
+    failed
 }
 
-#[derive(Debug)]
-enum Expr {
-    Number(i64),
-    Ident(String),
+// True while the input has an unclosed '(' or '{', so the REPL should keep
+// reading with a continuation prompt.
+fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0i32;
+    for tok in Lexer::new(input.as_bytes()).map_while(Result::ok) {
+        match tok.kind {
+            TokenKind::LParen | TokenKind::LBrace => depth += 1,
+            TokenKind::RParen | TokenKind::RBrace => depth -= 1,
+            // A block comment may span several inputs.
+            TokenKind::Invalid(e) if e.code == Code::UnterminatedComment => return true,
+            _ => {}
+        }
+        // A stray closer can never be balanced by more input.
+        if depth < 0 {
+            return false;
+        }
+    }
+    depth > 0
+}
 
-    Binary {
-        op: String,
-        left: Box<Expr>,
-        right: Box<Expr>,
-    },
+// One line per node, children indented under their parent. The nodes still
+// to print are kept on a stack, since a long run of operators is as deep
+// as it is long.
+fn dump_ast(root: &Expr, out: &mut String) {
+    let mut todo = vec![(root, 0)];
+    while let Some((expr, depth)) = todo.pop() {
+        let children = dump_node(expr, depth, out);
+        todo.extend(children.into_iter().rev().map(|c| (c, depth + 1)));
+    }
 }
 
-struct Parser {
-    tokens: Vec<Token>,
-    pos: usize,
+fn dump_node<'e>(expr: &'e Expr, depth: usize, out: &mut String) -> Vec<&'e Expr> {
+    let pad = "  ".repeat(depth);
+    let mut children: Vec<&Expr> = Vec::new();
+
+    let head = match expr {
+        Expr::Number { value, .. } => format!("Number {}", value),
+        Expr::Bool(b) => format!("Bool {}", b),
+        Expr::Str(s) => format!("Str {:?}", s),
+        Expr::Ident { name, .. } => format!("Ident {}", name),
+        Expr::Let { name, value, .. } => {
+            children.push(value);
+            format!("Let {}", name)
+        }
+        Expr::Assign { name, value, .. } => {
+            children.push(value);
+            format!("Assign {}", name)
+        }
+        Expr::FnDef { name, params, body, .. } => {
+            children.push(body);
+            format!("FnDef {}({})", name, params.join(", "))
+        }
+        Expr::Call { name, args, .. } => {
+            children.extend(args);
+            format!("Call {}", name)
+        }
+        Expr::Unary { op, operand, .. } => {
+            children.push(operand);
+            format!("Unary {}", op)
+        }
+        Expr::Binary { op, left, right, .. } => {
+            children.extend([&**left, &**right]);
+            format!("Binary {}", op)
+        }
+        Expr::Block { stmts, .. } => {
+            children.extend(stmts);
+            "Block".to_string()
+        }
+        Expr::If { cond, then, otherwise, .. } => {
+            children.extend([&**cond, &**then]);
+            children.extend(otherwise.as_deref());
+            "If".to_string()
+        }
+        Expr::While { cond, body, .. } => {
+            children.extend([&**cond, &**body]);
+            "While".to_string()
+        }
+        Expr::Error { .. } => "Error".to_string(),
+    };
+
+    out.push_str(&format!("{}{}\n", pad, head));
+    children
 }
 
-impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, pos: 0 }
+// The tree a statement runs as, after the optimizer when `optimized`.
+fn print_ast(stmt: &Expr, optimized: bool, mode: Mode) -> Result<(), Error> {
+    let mut out = String::new();
+    if optimized {
+        dump_ast(&optimize(stmt, mode)?, &mut out);
+    } else {
+        dump_ast(stmt, &mut out);
     }
+    print!("{}", out);
+    Ok(())
+}
 
-    fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.pos)
+// --emit=ast|optimized-ast: prints each statement's tree instead of running
+// it. Returns the number of errors, like `run_script`.
+fn emit_script(input: impl BufRead, optimized: bool, mode: Mode) -> usize {
+    let mut statements = Statements::new(input, 1);
+    let mut failed = 0;
+    loop {
+        let Statement { tokens, text, line } = match statements.next_statement() {
+            Ok(Some(next)) => next,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("input error: {}", e);
+                failed += 1;
+                break;
+            }
+        };
+        let (stmts, errors) = Parser::new(tokens).parse_program();
+        failed += errors.len();
+        for e in errors {
+            render_error(&text, line, e);
+        }
+        for stmt in &stmts {
+            if let Err(e) = print_ast(stmt, optimized, mode) {
+                render_error(&text, line, e);
+                failed += 1;
+            }
+        }
     }
+    failed
+}
 
-    fn next(&mut self) -> Option<Token> {
-        if self.pos >= self.tokens.len() { return None; }
-        let t = self.tokens[self.pos].clone();
-        self.pos += 1;
-        Some(t)
-    }
+const META_COMMANDS: &str = ":tokens <expr>; :ast [--optimized] <expr>; :diff <expr>, <var>; :env; :reset; :load <file>; :time <expr>";
 
-    fn parse_expression(&mut self) -> Result<Expr, Error> {
-        let left_tok = self.next().ok_or(Error::new("unexpected end", 0))?;
+fn meta_command(line: &str, line_no: usize, interp: &mut Interpreter) {
+    let (cmd, arg) = line.split_once(' ').unwrap_or((line, ""));
+    let arg = arg.trim();
 
-        let left = match left_tok.kind {
-            TokenKind::Number(n) => Expr::Number(n),
-            TokenKind::Ident(s) => Expr::Ident(s),
-            _ => return Err(Error::new("expected value", left_tok.col)),
-        };
+    match cmd {
+        ":tokens" => {
+            for tok in Lexer::new(arg.as_bytes()).map_while(Result::ok) {
+                let pos = format!("{}:{}", tok.line, tok.col);
+                match tok.kind {
+                    TokenKind::Invalid(e) => println!("{:<6} invalid: {}", pos, e.msg),
+                    kind => println!("{:<6} {:?}", pos, kind),
+                }
+            }
+        }
 
-        if let Some(op_tok) = self.peek() {
-            if let TokenKind::Operator(op) = &op_tok.kind {
-                let op = op.clone();
-                let col = op_tok.col;
-                self.next();
-
-                let right_tok = self.next().ok_or(Error::new("missing rhs", col))?;
-
-                let right = match right_tok.kind {
-                    TokenKind::Number(n) => Expr::Number(n),
-                    TokenKind::Ident(s) => Expr::Ident(s),
-                    _ => return Err(Error::new("invalid rhs", right_tok.col)),
-                };
-
-                return Ok(Expr::Binary {
-                    op,
-                    left: Box::new(left),
-                    right: Box::new(right),
-                });
+        // Broken statements show up as `Error` nodes next to the good ones.
+        ":ast" => {
+            let (optimized, arg) = match arg.strip_prefix("--optimized") {
+                Some(rest) => (true, rest.trim_start()),
+                None => (false, arg),
+            };
+            let tokens = Lexer::new(arg.as_bytes()).map_while(Result::ok).collect();
+            let (stmts, errors) = Parser::new(tokens).parse_program();
+            for stmt in &stmts {
+                if let Err(e) = print_ast(stmt, optimized, interp.mode()) {
+                    render_error(arg, line_no, e);
+                }
+            }
+            for e in errors {
+                render_error(arg, line_no, e);
             }
         }
 
-        Ok(left)
-    }
-}
+        ":env" => {
+            for (name, value) in interp.globals() {
+                println!("{} = {}", name, value);
+            }
+            for (name, params) in interp.functions() {
+                println!("fn {}({})", name, params.join(", "));
+            }
+        }
 
-fn eval(expr: &Expr, env: &mut HashMap<String, i64>) -> Result<i64, String> {
-    match expr {
-        Expr::Number(n) => Ok(*n),
+        ":reset" => interp.reset(),
 
-        Expr::Ident(name) => env.get(name).copied()
-            .ok_or_else(|| format!("undefined '{}'", name)),
+        ":load" => match fs::read_to_string(arg) {
+            Ok(src) => {
+                run_script(src.as_bytes(), 1, interp, false);
+            }
+            Err(e) => eprintln!("file error: {}: {}", arg, e),
+        },
 
-        Expr::Binary { op, left, right } => {
-            let l = eval(left, env)?;
-            let r = eval(right, env)?;
+        ":time" => {
+            let start = Instant::now();
+            run_script(arg.as_bytes(), line_no, interp, false);
+            println!("took {:?}", start.elapsed());
+        }
 
-            match op.as_str() {
-                "+" => Ok(l + r),
-                "-" => Ok(l - r),
-                "*" => Ok(l * r),
-                "/" => {
-                    if r == 0 { return Err("division by zero".into()); }
-                    Ok(l / r)
+        // The variable comes after the last comma, so the expression may
+        // hold calls with several arguments.
+        ":diff" => {
+            let (expr, var) = arg.rsplit_once(',').unwrap_or((arg, ""));
+            let var = var.trim();
+            let is_name = matches!(&Lexer::new(var.as_bytes()).map_while(Result::ok).collect::<Vec<_>>()[..],
+                [tok] if matches!(tok.kind, TokenKind::Ident(_)));
+            if !is_name {
+                eprintln!("usage: :diff <expr>, <var>");
+                return;
+            }
+            let tokens = Lexer::new(expr.as_bytes()).map_while(Result::ok).collect();
+            let (stmts, errors) = Parser::new(tokens).parse_program();
+            if !errors.is_empty() {
+                for e in errors {
+                    render_error(arg, line_no, e);
                 }
-                _ => Err("unknown operator".into()),
+                return;
+            }
+            match &stmts[..] {
+                [stmt] => match diff(stmt, var, interp.mode()) {
+                    Ok(d) => {
+                        let text = fmt::expr(&d);
+                        println!("{}", text);
+                        if interp.mode().numeric == Numeric::Int && text.contains('/') {
+                            eprintln!("note: '/' here is exact division; run with --rational or --float to evaluate it");
+                        }
+                    }
+                    Err(e) => render_error(arg, line_no, e),
+                },
+                [] => {}
+                _ => eprintln!("usage: :diff <expr>, <var>"),
             }
         }
+
+        _ => {
+            eprintln!("unknown command '{}'", cmd);
+            eprintln!("commands are {}", META_COMMANDS);
+        }
     }
 }
 
-fn render_error(line: &str, line_no: usize, err: Error) {
-    eprintln!("error at line {}, col {}: {}", line_no, err.col + 1, err.msg);
-    eprintln!("{}", line);
-    eprintln!("{}^", " ".repeat(err.col));
-}
+fn repl(interp: &mut Interpreter) {
+    let mut editor = term::Editor::new();
+    let mut input = String::new();
+    // Lines entered so far, so diagnostics number REPL input like a file.
+    let mut line_no = 1;
 
-fn execute_line(line: &str, line_no: usize, env: &mut HashMap<String, i64>) -> Result<i64, ()> {
-    let tokens = match tokenize(line) {
-        Ok(t) => t,
-        Err(e) => {
-            render_error(line, line_no, e);
-            return Err(());
+    loop {
+        let prompt = if input.is_empty() { "> " } else { ".. " };
+        let line = match editor.read_line(prompt) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                line_no += input.lines().count();
+                input.clear();
+                continue;
+            }
+            Err(e) => {
+                eprintln!("input error: {}", e);
+                break;
+            }
+        };
+
+        if input.is_empty() && line.trim() == "quit" { break; }
+        if input.is_empty() && line.trim().is_empty() {
+            line_no += 1;
+            continue;
         }
-    };
+        editor.add_history(&line);
 
-    let mut parser = Parser::new(tokens);
+        if input.is_empty() && line.trim_start().starts_with(':') {
+            meta_command(line.trim(), line_no, interp);
+            line_no += 1;
+            continue;
+        }
 
-    let expr = match parser.parse_expression() {
-        Ok(e) => e,
-        Err(e) => {
-            render_error(line, line_no, e);
-            return Err(());
+        input.push_str(&line);
+        input.push('\n');
+        if is_incomplete(&input) {
+            continue;
         }
+
+        run_script(input.as_bytes(), line_no, interp, false);
+        line_no += input.lines().count();
+        input.clear();
+    }
+
+    editor.save();
+}
+
+fn read_spec(path: &str) -> Vec<spec::Rule> {
+    let src = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("file error: {}: {}", path, e);
+        process::exit(1);
+    });
+    spec::parse(&src).unwrap_or_else(|e| {
+        eprintln!("spec error: {}: {}", path, e);
+        process::exit(1);
+    })
+}
+
+// --dot[=nfa|dfa|min] [SPEC]: the built-in token set unless a spec is given.
+fn dot_command(which: &str, spec_path: Option<&str>) {
+    let rules = match spec_path {
+        Some(path) => read_spec(path),
+        None => spec::parse(TOKEN_SPEC).expect("built-in token spec is valid"),
     };
+    let nfa = Nfa::from_rules(&rules);
+    let out = match which {
+        "nfa" => dot::nfa(&nfa, &rules),
+        "dfa" => dot::dfa(&Dfa::from_nfa(&nfa), &rules, "dfa"),
+        "min" => dot::dfa(&Dfa::from_nfa(&nfa).minimize(), &rules, "min_dfa"),
+        _ => {
+            eprintln!("--dot takes nfa, dfa or min, not '{}'", which);
+            process::exit(2);
+        }
+    };
+    print!("{}", out);
+}
 
-    match eval(&expr, env) {
-        Ok(v) => Ok(v),
-        Err(e) => {
-            eprintln!("runtime error at line {}: {}", line_no, e);
-            Err(())
+// dfa-lexer gen SPEC [-o OUT]
+fn gen_command(args: &[String]) {
+    let mut spec_path = None;
+    let mut out_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(path) => out_path = Some(path),
+                None => {
+                    eprintln!("-o needs a file name");
+                    process::exit(2);
+                }
+            },
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option '{}'", arg);
+                process::exit(2);
+            }
+            _ => spec_path = Some(arg),
         }
     }
-}
+    let Some(spec_path) = spec_path else {
+        eprintln!("usage: dfa-lexer gen SPEC [-o OUT]");
+        process::exit(2);
+    };
 
-fn run_script(input: &str, env: &mut HashMap<String, i64>) {
-    for (i, line) in input.lines().enumerate() {
-        if line.trim().is_empty() { continue; }
+    let rules = read_spec(spec_path);
 
-        match execute_line(line, i + 1, env) {
-            Ok(v) => println!("{}", v),
-            Err(_) => break,
+    let dfa = Dfa::from_nfa(&Nfa::from_rules(&rules)).minimize();
+    let code = codegen::generate(&rules, &dfa, spec_path);
+    match out_path {
+        Some(path) => {
+            if let Err(e) = fs::write(path, code) {
+                eprintln!("file error: {}: {}", path, e);
+                process::exit(1);
+            }
         }
+        None => print!("{}", code),
     }
 }
 
-fn repl(env: &mut HashMap<String, i64>) {
-    let stdin = io::stdin();
-    let mut line = String::new();
-
-    loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
+// dfa-lexer fmt [--check] [FILE...]: rewrites each file in place, or stdin
+// to stdout without files. --check only lists the input that would change.
+fn fmt_command(args: &[String]) {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option '{}'", arg);
+                process::exit(2);
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
 
-        line.clear();
-        if stdin.read_line(&mut line).unwrap() == 0 {
-            break;
+    let mut failed = false;
+    let stdin = paths.is_empty();
+    if stdin {
+        paths.push("<stdin>");
+    }
+    for path in paths {
+        let mut src = String::new();
+        let read = if stdin { io::stdin().read_to_string(&mut src) } else { fs::File::open(path).and_then(|mut f| f.read_to_string(&mut src)) };
+        if let Err(e) = read {
+            eprintln!("file error: {}: {}", path, e);
+            failed = true;
+            continue;
         }
 
-        let line = line.trim();
-        if line == "quit" { break; }
-        if line.is_empty() { continue; }
+        let out = match fmt::format(&src) {
+            Ok(out) => out,
+            Err(errors) => {
+                eprintln!("{}: not formatted because of syntax errors", path);
+                for e in errors {
+                    render_error(&src, 1, e);
+                }
+                failed = true;
+                continue;
+            }
+        };
+        if check {
+            if out != src {
+                println!("{}", path);
+                failed = true;
+            }
+        } else if stdin {
+            print!("{}", out);
+        } else if out != src
+            && let Err(e) = fs::write(path, out)
+        {
+            eprintln!("file error: {}: {}", path, e);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
 
-        match execute_line(line, 1, env) {
-            Ok(v) => println!("{}", v),
-            Err(_) => {}
+// dfa-lexer lsp: a language server on stdin/stdout. Exits 0 when the client
+// sent `shutdown` before `exit`, as the protocol asks.
+fn lsp_command() {
+    match lsp::serve(io::stdin().lock(), io::stdout().lock()) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("lsp: {}", e);
+            process::exit(1);
         }
     }
 }
 
 fn main() {
-    let mut env = HashMap::new();
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("gen") => return gen_command(&args[1..]),
+        Some("fmt") => return fmt_command(&args[1..]),
+        Some("lsp") => return lsp_command(),
+        _ => {}
+    }
 
-    if args.len() > 1 {
-        let content = match fs::read_to_string(&args[1]) {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("file error: {}", e);
+    let mut mode = Mode::default();
+    let mut backend = Backend::default();
+    let mut file = None;
+    let mut keep_going = false;
+    let mut dot = None;
+    let mut emit = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--dump-stats" => {
+                dump_stats();
                 return;
             }
-        };
+            "--bigint" => mode.bigint = true,
+            "--rational" => mode.numeric = Numeric::Rational,
+            "--float" => mode.numeric = Numeric::Float,
+            "--keep-going" => keep_going = true,
+            "--vm" => backend = Backend::Vm,
+            "--dot" => dot = Some("min".to_string()),
+            _ if arg.starts_with("--dot=") => dot = Some(arg["--dot=".len()..].to_string()),
+            "--emit=ast" => emit = Some(false),
+            "--emit=optimized-ast" => emit = Some(true),
+            _ if arg.starts_with("--emit=") => {
+                eprintln!("--emit takes ast or optimized-ast, not '{}'", &arg["--emit=".len()..]);
+                process::exit(2);
+            }
+            _ if arg.starts_with("--") => {
+                eprintln!("unknown option '{}'", arg);
+                process::exit(2);
+            }
+            _ => file = Some(arg),
+        }
+    }
 
-        run_script(&content, &mut env);
+    if let Some(which) = dot {
+        dot_command(&which, file.as_deref());
         return;
     }
 
-    if stdin_is_tty() {
-        repl(&mut env);
+    let mut interp = Interpreter::new().with_mode(mode).with_backend(backend);
+    let failed = if let Some(optimized) = emit {
+        let input: Box<dyn BufRead> = match &file {
+            Some(path) => match fs::File::open(path) {
+                Ok(f) => Box::new(BufReader::new(f)),
+                Err(e) => {
+                    eprintln!("file error: {}", e);
+                    process::exit(1);
+                }
+            },
+            None => Box::new(io::stdin().lock()),
+        };
+        emit_script(input, optimized, mode)
+    } else if let Some(path) = file {
+        match fs::File::open(&path) {
+            Ok(f) => run_script(BufReader::new(f), 1, &mut interp, keep_going),
+            Err(e) => {
+                eprintln!("file error: {}", e);
+                process::exit(1);
+            }
+        }
+    } else if stdin_is_tty() {
+        repl(&mut interp);
         return;
-    }
+    } else {
+        run_script(io::stdin().lock(), 1, &mut interp, keep_going)
+    };
 
-    let mut input = String::new();
-    io::stdin().read_to_string(&mut input).unwrap();
-    run_script(&input, &mut env);
+    if failed > 0 {
+        process::exit(1);
+    }
 }
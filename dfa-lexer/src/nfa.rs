@@ -0,0 +1,99 @@
+use crate::regex::{CharSet, Regex};
+use crate::spec::Rule;
+
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    pub eps: Vec<usize>,
+    pub edges: Vec<(usize, usize)>,
+}
+
+// Thompson NFA. Edges refer to character sets by index into `sets` so the
+// subset construction can derive a shared alphabet from them.
+#[derive(Debug, Clone)]
+pub struct Nfa {
+    pub states: Vec<State>,
+    pub sets: Vec<CharSet>,
+    pub start: usize,
+    pub accept: Vec<Option<usize>>,
+}
+
+impl Nfa {
+    pub fn from_rules(rules: &[Rule]) -> Self {
+        let mut nfa = Nfa { states: vec![State::default()], sets: Vec::new(), start: 0, accept: vec![None] };
+
+        for (index, rule) in rules.iter().enumerate() {
+            let (start, end) = nfa.fragment(&rule.regex);
+            nfa.states[0].eps.push(start);
+            nfa.accept[end] = Some(index);
+        }
+
+        nfa
+    }
+
+    fn add_state(&mut self) -> usize {
+        self.states.push(State::default());
+        self.accept.push(None);
+        self.states.len() - 1
+    }
+
+    fn fragment(&mut self, re: &Regex) -> (usize, usize) {
+        let s = self.add_state();
+        let e = self.add_state();
+
+        match re {
+            Regex::Empty => self.states[s].eps.push(e),
+            Regex::Set(set) => {
+                self.sets.push(set.clone());
+                let id = self.sets.len() - 1;
+                self.states[s].edges.push((id, e));
+            }
+            Regex::Concat(items) => {
+                let mut prev = s;
+                for item in items {
+                    let (is, ie) = self.fragment(item);
+                    self.states[prev].eps.push(is);
+                    prev = ie;
+                }
+                self.states[prev].eps.push(e);
+            }
+            Regex::Alt(branches) => {
+                for branch in branches {
+                    let (bs, be) = self.fragment(branch);
+                    self.states[s].eps.push(bs);
+                    self.states[be].eps.push(e);
+                }
+            }
+            Regex::Star(inner) | Regex::Plus(inner) | Regex::Optional(inner) => {
+                let (is, ie) = self.fragment(inner);
+                self.states[s].eps.push(is);
+                self.states[ie].eps.push(e);
+                if !matches!(re, Regex::Optional(_)) {
+                    self.states[ie].eps.push(is);
+                }
+                if !matches!(re, Regex::Plus(_)) {
+                    self.states[s].eps.push(e);
+                }
+            }
+        }
+
+        (s, e)
+    }
+
+    pub fn closure(&self, seed: &[usize]) -> Vec<usize> {
+        let mut seen = vec![false; self.states.len()];
+        let mut stack = seed.to_vec();
+        let mut out = Vec::new();
+
+        while let Some(s) = stack.pop() {
+            if seen[s] {
+                continue;
+            }
+            seen[s] = true;
+            out.push(s);
+            stack.extend(&self.states[s].eps);
+        }
+
+        out.sort_unstable();
+        out
+    }
+}
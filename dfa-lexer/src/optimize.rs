@@ -0,0 +1,292 @@
+// Constant folding and algebraic simplification, run on each statement
+// before either backend sees it. A rewrite never changes what a statement
+// prints, returns or fails with, except that dividing a constant by a
+// constant zero is reported here, before the statement starts running.
+use std::rc::Rc;
+
+use crate::diag::{Code, Error, Span};
+use crate::eval::{builtin, call_builtin, literal};
+use crate::parser::Expr;
+use crate::value::{self, Mode, Numeric, Value};
+
+// What an expression yields whenever it does not fail. `Exact` numbers are
+// integers and rationals, which unlike floats have no -0.0, NaN or infinity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Exact,
+    Number,
+    Bool,
+    Str,
+    Unknown,
+}
+
+impl Kind {
+    fn numeric(self) -> bool {
+        matches!(self, Kind::Exact | Kind::Number)
+    }
+}
+
+fn kind(e: &Expr, mode: Mode) -> Kind {
+    match e {
+        Expr::Number { value: Value::Float(_), .. } => Kind::Number,
+        Expr::Number { .. } => Kind::Exact,
+        Expr::Bool(_) => Kind::Bool,
+        Expr::Str(_) => Kind::Str,
+        Expr::Unary { op, .. } if op == "!" => Kind::Bool,
+        Expr::Unary { operand, .. } if kind(operand, mode) == Kind::Exact => Kind::Exact,
+        Expr::Unary { .. } => Kind::Number,
+        Expr::Binary { .. } => {
+            let (first, rest) = e.chain();
+            rest.iter().fold(kind(first, mode), |l, (op, _, right)| binary_kind(op, l, kind(right, mode), mode))
+        }
+        _ => Kind::Unknown,
+    }
+}
+
+fn binary_kind(op: &str, l: Kind, r: Kind, mode: Mode) -> Kind {
+    let exact = l == Kind::Exact && r == Kind::Exact;
+    match op {
+        "+" | "-" | "*" if exact => Kind::Exact,
+        "/" if exact && mode.numeric != Numeric::Float => Kind::Exact,
+        "+" if l == Kind::Str && r == Kind::Str => Kind::Str,
+        "+" if !(l.numeric() && r.numeric()) => Kind::Unknown,
+        "+" | "-" | "*" | "/" => Kind::Number,
+        _ => Kind::Bool,
+    }
+}
+
+fn constant(e: &Expr, mode: Mode) -> Option<Value> {
+    match e {
+        Expr::Number { value, span } => literal(value, *span, mode).ok(),
+        Expr::Bool(b) => Some(Value::Bool(*b)),
+        Expr::Str(s) => Some(Value::Str(s.clone())),
+        _ => None,
+    }
+}
+
+// Folded numbers point at the operator or call that produced them.
+pub(crate) fn from_value(value: Value, span: Span) -> Expr {
+    match value {
+        Value::Bool(b) => Expr::Bool(b),
+        Value::Str(s) => Expr::Str(s),
+        value => Expr::Number { value, span },
+    }
+}
+
+fn is_int(e: &Expr, n: i64) -> bool {
+    matches!(e, Expr::Number { value: Value::Int(v), .. } if *v == n)
+}
+
+fn nothing(span: Span) -> Expr {
+    Expr::Block { stmts: Vec::new(), span }
+}
+
+// Arms with temporaries live in their own functions, as in `eval`, so a
+// deep tree does not nest one large frame per level.
+pub fn optimize(expr: &Expr, mode: Mode) -> Result<Expr, Error> {
+    match expr {
+        Expr::Unary { op, span, operand } => optimize(operand, mode).map(|operand| unary(op, *span, operand, mode)),
+        Expr::Binary { .. } => chain(expr, mode),
+        Expr::If { cond, then, otherwise, span } => branch(cond, then, otherwise.as_deref(), *span, mode),
+        Expr::Number { .. } | Expr::Bool(_) | Expr::Str(_) | Expr::Ident { .. } | Expr::Error { .. } => Ok(expr.clone()),
+        _ => rebuild(expr, mode),
+    }
+}
+
+// Statements, calls and blocks: their parts are optimized in place.
+fn rebuild(expr: &Expr, mode: Mode) -> Result<Expr, Error> {
+    let opt = |e: &Expr| optimize(e, mode);
+    Ok(match expr {
+        Expr::Let { name, value, span } => Expr::Let { name: name.clone(), value: Box::new(opt(value)?), span: *span },
+        Expr::Assign { name, value, span } => Expr::Assign { name: name.clone(), value: Box::new(opt(value)?), span: *span },
+        Expr::FnDef { name, params, body, span } => {
+            Expr::FnDef { name: name.clone(), params: params.clone(), body: Rc::new(opt(body)?), span: *span }
+        }
+        Expr::Call { name, args, span } => {
+            let args = args.iter().map(opt).collect::<Result<Vec<_>, _>>()?;
+            fold_call(name, args, *span, mode)
+        }
+        Expr::Block { stmts, span } => Expr::Block { stmts: stmts.iter().map(opt).collect::<Result<_, _>>()?, span: *span },
+        Expr::While { cond, body, span } => {
+            let cond = opt(cond)?;
+            match constant(&cond, mode) {
+                Some(Value::Bool(false)) => nothing(*span),
+                _ => Expr::While { cond: Box::new(cond), body: Box::new(opt(body)?), span: *span },
+            }
+        }
+        _ => expr.clone(),
+    })
+}
+
+// A constant condition drops the branch that cannot run, unchecked.
+fn branch(cond: &Expr, then: &Expr, otherwise: Option<&Expr>, span: Span, mode: Mode) -> Result<Expr, Error> {
+    let cond = optimize(cond, mode)?;
+    Ok(match (constant(&cond, mode), otherwise) {
+        (Some(Value::Bool(true)), _) => optimize(then, mode)?,
+        (Some(Value::Bool(false)), Some(otherwise)) => optimize(otherwise, mode)?,
+        (Some(Value::Bool(false)), None) => nothing(span),
+        _ => {
+            let otherwise = otherwise.map(|o| optimize(o, mode)).transpose()?.map(Box::new);
+            Expr::If { cond: Box::new(cond), then: Box::new(optimize(then, mode)?), otherwise, span }
+        }
+    })
+}
+
+// Builtins other than print are pure and cannot be redefined, so a call
+// with constant arguments can run now. One that fails is left for the
+// backend to report.
+fn fold_call(name: &str, args: Vec<Expr>, span: Span, mode: Mode) -> Expr {
+    let values: Option<Vec<Value>> = args.iter().map(|a| constant(a, mode)).collect();
+    if let (Some((Some(arity), f)), Some(values)) = (builtin(name), values)
+        && arity == values.len()
+        && let Ok(v) = call_builtin(name, f, &values, span, mode)
+    {
+        return from_value(v, span);
+    }
+    Expr::Call { name: name.to_string(), args, span }
+}
+
+fn unary(op: &str, span: Span, operand: Expr, mode: Mode) -> Expr {
+    let folded = match (op, constant(&operand, mode)) {
+        ("-", Some(v)) => v.negate(mode).ok(),
+        ("!", Some(Value::Bool(b))) => Some(Value::Bool(!b)),
+        _ => None,
+    };
+    if let Some(v) = folded {
+        return from_value(v, span);
+    }
+    match operand {
+        // `!!x` is x when x is a bool.
+        Expr::Unary { op: inner, operand: x, .. } if op == "!" && inner == "!" && kind(&x, mode) == Kind::Bool => *x,
+        operand => Expr::Unary { op: op.to_string(), span, operand: Box::new(operand) },
+    }
+}
+
+// Folds a run of operators left to right. The kind of what has been built
+// so far is carried along rather than worked out again at every step.
+fn chain(expr: &Expr, mode: Mode) -> Result<Expr, Error> {
+    let (first, rest) = expr.chain();
+    let l = optimize(first, mode)?;
+    let mut acc = (kind(&l, mode), l);
+    for (op, span, right) in rest {
+        acc = binary(op, span, acc, right, mode)?;
+    }
+    Ok(acc.1)
+}
+
+// Identities only apply when the operand's kind makes them exact: `x + 0`
+// turns -0.0 into 0.0 and `x * 1` hides the error a string would raise.
+// `x - x` is left alone: x may be unbound, a string or an infinity, and
+// when it is a constant, folding already gives 0.
+fn binary(op: &str, span: Span, (lk, l): (Kind, Expr), right: &Expr, mode: Mode) -> Result<(Kind, Expr), Error> {
+    let rebuild = |l, r, rk| (binary_kind(op, lk, rk, mode), Expr::Binary { op: op.to_string(), span, left: Box::new(l), right: Box::new(r) });
+
+    if op == "&&" || op == "||" {
+        // `false && x` and `true || x` never evaluate x.
+        let decides = op == "||";
+        let left_bool = match constant(&l, mode) {
+            Some(Value::Bool(b)) if b == decides => return Ok((Kind::Bool, Expr::Bool(b))),
+            Some(Value::Bool(_)) => true,
+            _ => false,
+        };
+        let r = optimize(right, mode)?;
+        let rk = kind(&r, mode);
+        return Ok(match (&l, &r) {
+            _ if left_bool && rk == Kind::Bool => (rk, r),
+            (_, Expr::Bool(b)) if *b != decides && lk == Kind::Bool => (lk, l),
+            _ => rebuild(l, r, rk),
+        });
+    }
+
+    let r = optimize(right, mode)?;
+    if let (Some(a), Some(b)) = (constant(&l, mode), constant(&r, mode)) {
+        match value::binary(op, &a, &b, mode) {
+            Ok(v) => {
+                let folded = from_value(v, span);
+                return Ok((kind(&folded, mode), folded));
+            }
+            Err(e) if e.code == Code::DivisionByZero => {
+                return Err(Error::new(Code::DivisionByZero, "division by zero", span)
+                    .with_note("both operands are constants, so this is caught before the statement runs"));
+            }
+            Err(_) => {}
+        }
+    }
+
+    let rk = kind(&r, mode);
+    Ok(match op {
+        "*" if is_int(&l, 1) && rk.numeric() => (rk, r),
+        "*" | "/" if is_int(&r, 1) && lk.numeric() => (lk, l),
+        "-" if is_int(&r, 0) && lk.numeric() => (lk, l),
+        "+" if is_int(&l, 0) && rk == Kind::Exact => (rk, r),
+        "+" if is_int(&r, 0) && lk == Kind::Exact => (lk, l),
+        _ => rebuild(l, r, rk),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::Parser;
+
+    fn shape(e: &Expr) -> String {
+        match e {
+            Expr::Number { value, .. } => value.to_string(),
+            Expr::Bool(b) => b.to_string(),
+            Expr::Str(s) => format!("{:?}", s),
+            Expr::Ident { name, .. } => name.clone(),
+            Expr::Unary { op, operand, .. } => format!("({} {})", op, shape(operand)),
+            Expr::Binary { op, left, right, .. } => format!("({} {} {})", op, shape(left), shape(right)),
+            Expr::Call { name, args, .. } => format!("({} {})", name, args.iter().map(shape).collect::<Vec<_>>().join(" ")),
+            Expr::Block { stmts, .. } => format!("{{{}}}", stmts.iter().map(shape).collect::<Vec<_>>().join("; ")),
+            Expr::Let { name, value, .. } => format!("(let {} {})", name, shape(value)),
+            other => format!("{:?}", other),
+        }
+    }
+
+    fn optimized(src: &str, mode: Mode) -> Result<String, Error> {
+        let stmt = Parser::new(lex(src)).parse_statement().unwrap();
+        optimize(&stmt, mode).map(|e| shape(&e))
+    }
+
+    fn opt(src: &str) -> String {
+        optimized(src, Mode::default()).unwrap()
+    }
+
+    #[test]
+    fn folds_constant_subtrees() {
+        assert_eq!(opt("2 * 3 + x"), "(+ 6 x)");
+        assert_eq!(opt("let y = -(4 - 5) * pow(2, 10)"), "(let y 1024)");
+        assert_eq!(opt("\"a\" + \"b\" == \"ab\" && !false"), "true");
+        assert_eq!(opt("7 / 2"), "3");
+        assert_eq!(optimized("7 / 2", Mode { numeric: Numeric::Rational, bigint: false }).unwrap(), "7/2");
+        // Failing constants stay for the backend to report.
+        assert_eq!(opt("9223372036854775807 + 1"), "(+ 9223372036854775807 1)");
+        assert_eq!(opt("if 1 < 2 { a } else { b }"), "{a}");
+        assert_eq!(opt("while 1 > 2 { a }"), "{}");
+    }
+
+    #[test]
+    fn identities_only_where_the_kind_allows() {
+        assert_eq!(opt("-x * 1"), "(- x)");
+        assert_eq!(opt("1 * (a - b) / 1 - 0"), "(- a b)");
+        assert_eq!(opt("(9223372036854775807 + 1) + 0"), "(+ 9223372036854775807 1)");
+        assert_eq!(opt("!!(a < b) && true"), "(< a b)");
+        assert_eq!(opt("false && f()"), "false");
+        // x could be a string or -0.0, or unbound.
+        assert_eq!(opt("x * 1"), "(* x 1)");
+        assert_eq!(opt("-x + 0"), "(+ (- x) 0)");
+        assert_eq!(opt("x - x"), "(- x x)");
+        assert_eq!(opt("!!x"), "(! (! x))");
+    }
+
+    #[test]
+    fn constant_division_by_zero_fails_at_the_operator() {
+        let err = optimized("x + (4 / (2 - 2))", Mode::default()).unwrap_err();
+        assert_eq!((err.code, err.span), (Code::DivisionByZero, Span::new(7, 8)));
+        assert!(optimized("x / 0", Mode::default()).is_ok());
+        assert!(optimized("if false { 1 / 0 } else { 2 }", Mode::default()).is_ok());
+        assert!(optimized("true || 1 / 0 == 0", Mode::default()).is_ok());
+    }
+}
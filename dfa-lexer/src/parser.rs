@@ -0,0 +1,657 @@
+use std::io::{self, BufRead};
+use std::rc::Rc;
+
+use crate::diag::{Code, Error, Span};
+use crate::lexer::{Lexer, Token, TokenKind};
+use crate::value::Value;
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number {
+        value: Value,
+        span: Span,
+    },
+
+    Bool(bool),
+
+    Str(Rc<str>),
+
+    Ident {
+        name: String,
+        span: Span,
+    },
+
+    // `span` covers the name being bound or assigned.
+    Let {
+        name: String,
+        value: Box<Expr>,
+        span: Span,
+    },
+
+    Assign {
+        name: String,
+        value: Box<Expr>,
+        span: Span,
+    },
+
+    FnDef {
+        name: String,
+        params: Vec<String>,
+        body: Rc<Expr>,
+        span: Span,
+    },
+
+    Call {
+        name: String,
+        args: Vec<Expr>,
+        span: Span,
+    },
+
+    Unary {
+        op: String,
+        span: Span,
+        operand: Box<Expr>,
+    },
+
+    Binary {
+        op: String,
+        span: Span,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+
+    // `span` runs from '{' to '}'.
+    Block {
+        stmts: Vec<Expr>,
+        span: Span,
+    },
+
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Option<Box<Expr>>,
+        span: Span,
+    },
+
+    While {
+        cond: Box<Expr>,
+        body: Box<Expr>,
+        span: Span,
+    },
+
+    // Stands in for a statement that failed to parse, covering the tokens
+    // skipped to recover from it.
+    Error {
+        span: Span,
+    },
+}
+
+impl Expr {
+    pub fn is_statement(&self) -> bool {
+        matches!(self, Expr::Let { .. } | Expr::Assign { .. } | Expr::FnDef { .. } | Expr::While { .. })
+    }
+
+    // A run of operators like `a + b - c * d` parses to a spine of `Binary`
+    // nodes down the left, as long as the run. Walkers take it apart with
+    // this and loop instead of recursing once per operator: the leftmost
+    // operand, then each operator with its span and right operand, in
+    // evaluation order.
+    pub(crate) fn chain(&self) -> (&Expr, Vec<(&str, Span, &Expr)>) {
+        let mut rest = Vec::new();
+        let mut first = self;
+        while let Expr::Binary { op, span, left, right } = first {
+            rest.push((op.as_str(), *span, &**right));
+            first = left;
+        }
+        rest.reverse();
+        (first, rest)
+    }
+}
+
+pub(crate) const PREFIX_BP: u8 = 30;
+
+// (left, right) binding powers; right > left makes the operator left-associative
+pub(crate) fn infix_binding_power(op: &str) -> Option<(u8, u8)> {
+    match op {
+        "||" => Some((1, 2)),
+        "&&" => Some((3, 4)),
+        "==" | "!=" => Some((5, 6)),
+        "<" | "<=" | ">" | ">=" => Some((7, 8)),
+        "+" | "-" => Some((10, 11)),
+        "*" | "/" => Some((20, 21)),
+        _ => None,
+    }
+}
+
+// Parentheses, blocks, prefix operators and operands of a tighter operator
+// add a level that everything walking the tree recurses into. Deeper input
+// is a syntax error rather than a stack overflow later on. A run of
+// operators at one level is walked in a loop, so it may be any length.
+const MAX_NESTING: usize = 128;
+
+fn is_kind(tok: Option<&Token>, kind: &TokenKind) -> bool {
+    tok.is_some_and(|t| std::mem::discriminant(&t.kind) == std::mem::discriminant(kind))
+}
+
+// Statements end at a newline, ';', the '}' closing their block, or the end
+// of input. Newlines are also allowed after binary operators, inside call
+// arguments and parentheses, and before `else`.
+// Statement boundaries are also where parsing recovers from a syntax error:
+// the broken statement becomes `Expr::Error` and `errors` keeps the error.
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+    nesting: usize,
+    errors: Vec<Error>,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self { tokens, pos: 0, depth: 0, nesting: 0, errors: Vec::new() }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        if self.pos >= self.tokens.len() { return None; }
+        let t = self.tokens[self.pos].clone();
+        self.pos += 1;
+        Some(t)
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn skip_newlines(&mut self) {
+        while is_kind(self.peek(), &TokenKind::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    pub fn skip_separators(&mut self) {
+        while is_kind(self.peek(), &TokenKind::Newline) || is_kind(self.peek(), &TokenKind::Semi) {
+            self.pos += 1;
+        }
+    }
+
+    // After an error, skip the rest of the statement and any blocks opened
+    // in it. Inside a block, the '}' closing it is left for `parse_block`.
+    // Returns the end of the last token skipped.
+    fn synchronize(&mut self, err: &Error) -> usize {
+        // The error may have consumed the boundary it tripped over.
+        if let Some(prev) = self.pos.checked_sub(1).and_then(|i| self.tokens.get(i))
+            && matches!(prev.kind, TokenKind::Newline | TokenKind::Semi | TokenKind::RBrace)
+            && prev.span == err.span
+        {
+            self.pos -= 1;
+        }
+
+        let mut end = err.span.end;
+        let mut open = 0usize;
+        while let Some(tok) = self.peek() {
+            match tok.kind {
+                TokenKind::Newline | TokenKind::Semi if open == 0 => break,
+                TokenKind::RBrace if open == 0 && self.depth > 0 => return end,
+                TokenKind::LBrace => open += 1,
+                TokenKind::RBrace => open = open.saturating_sub(1),
+                _ => {}
+            }
+            end = end.max(tok.span.end);
+            self.pos += 1;
+        }
+        self.next();
+        end
+    }
+
+    // One statement, or `Expr::Error` after recording why it failed.
+    fn statement_or_error(&mut self) -> Expr {
+        let (start, depth, nesting) = (self.peek_span().start, self.depth, self.nesting);
+        match self.statement() {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                self.depth = depth;
+                self.nesting = nesting;
+                let end = self.synchronize(&e);
+                self.errors.push(e);
+                Expr::Error { span: Span::new(start, end) }
+            }
+        }
+    }
+
+    // Parses the next statement and returns the first syntax error in it, if
+    // any. Either way the parser is left at the start of the next statement.
+    pub fn parse_statement(&mut self) -> Result<Expr, Error> {
+        let stmt = self.statement_or_error();
+        match self.errors.drain(..).next() {
+            Some(e) => Err(e),
+            None => Ok(stmt),
+        }
+    }
+
+    // Every statement left, with `Expr::Error` in place of the broken ones,
+    // and every syntax error in source order.
+    pub fn parse_program(&mut self) -> (Vec<Expr>, Vec<Error>) {
+        let mut stmts = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.at_end() {
+                return (stmts, std::mem::take(&mut self.errors));
+            }
+            stmts.push(self.statement_or_error());
+        }
+    }
+
+    fn eof_span(&self) -> Span {
+        let last = self.tokens.iter().rev().find(|t| !matches!(t.kind, TokenKind::Newline));
+        let end = last.map_or(0, |t| t.span.end);
+        Span::new(end, end)
+    }
+
+    fn peek_span(&self) -> Span {
+        self.peek().map_or(self.eof_span(), |t| t.span)
+    }
+
+    fn nest(&mut self, span: Span) -> Result<(), Error> {
+        self.nesting += 1;
+        if self.nesting > MAX_NESTING {
+            return Err(Error::new(Code::NestingLimit, "expression is nested too deeply", span)
+                .with_note(format!("parentheses, blocks and operators may nest at most {} deep", MAX_NESTING))
+                .with_help("split it into smaller statements with `let`"));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<Expr, Error> {
+        let second = self.tokens.get(self.pos + 1).map(|t| &t.kind);
+
+        let stmt = match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Let) => {
+                self.next();
+                let span = self.peek_span();
+                let name = self.expect_ident()?;
+                self.expect_assign()?;
+                let value = self.parse_expr(0)?;
+                Expr::Let { name, value: Box::new(value), span }
+            }
+
+            Some(TokenKind::Ident(_)) if matches!(second, Some(TokenKind::Assign)) => {
+                let span = self.peek_span();
+                let name = self.expect_ident()?;
+                self.expect_assign()?;
+                let value = self.parse_expr(0)?;
+                Expr::Assign { name, value: Box::new(value), span }
+            }
+
+            Some(TokenKind::Fn) => self.parse_fn_def()?,
+
+            Some(TokenKind::While) => {
+                let span = self.next().unwrap().span;
+                let cond = self.parse_expr(0)?;
+                let body = self.parse_block()?;
+                Expr::While { cond: Box::new(cond), body: Box::new(body), span }
+            }
+
+            _ => self.parse_expr(0)?,
+        };
+
+        self.expect_terminator()?;
+        Ok(stmt)
+    }
+
+    fn expect_terminator(&mut self) -> Result<(), Error> {
+        match self.peek() {
+            None | Some(Token { kind: TokenKind::RBrace, .. }) => Ok(()),
+            Some(Token { kind: TokenKind::Newline | TokenKind::Semi, .. }) => {
+                self.next();
+                Ok(())
+            }
+            Some(Token { kind: TokenKind::RParen, span, .. }) => {
+                Err(Error::new(Code::UnbalancedParen, "unmatched ')'", *span))
+            }
+            Some(Token { kind: TokenKind::Assign, span, .. }) => Err(
+                Error::new(Code::InvalidAssignment, "invalid assignment target", *span)
+                    .with_help("only a plain variable name can appear left of '='"),
+            ),
+            Some(Token { kind: TokenKind::Invalid(e), .. }) => Err(e.clone()),
+            Some(tok) => Err(Error::new(Code::UnexpectedToken, "unexpected token after expression", tok.span)
+                .with_help("put each statement on its own line or separate them with ';'")),
+        }
+    }
+
+    fn parse_fn_def(&mut self) -> Result<Expr, Error> {
+        self.next();
+        let span = self.peek_span();
+        let name = self.expect_ident()?;
+        self.expect(TokenKind::LParen, "'('")?;
+
+        let mut params: Vec<String> = Vec::new();
+        if !is_kind(self.peek(), &TokenKind::RParen) {
+            loop {
+                let param_span = self.peek_span();
+                let param = self.expect_ident()?;
+                if params.contains(&param) {
+                    let msg = format!("duplicate parameter '{}'", param);
+                    return Err(Error::new(Code::DuplicateParameter, msg, param_span));
+                }
+                params.push(param);
+
+                if !is_kind(self.peek(), &TokenKind::Comma) {
+                    break;
+                }
+                self.next();
+            }
+        }
+        self.expect(TokenKind::RParen, "')'")?;
+
+        let body = if is_kind(self.peek(), &TokenKind::LBrace) {
+            self.parse_block()?
+        } else {
+            self.expect_assign()?;
+            self.parse_expr(0)?
+        };
+        Ok(Expr::FnDef { name, params, body: Rc::new(body), span })
+    }
+
+    fn parse_block(&mut self) -> Result<Expr, Error> {
+        let open = self.peek_span();
+        self.expect(TokenKind::LBrace, "'{'")?;
+        self.nest(open)?;
+        self.depth += 1;
+
+        let mut stmts = Vec::new();
+        let close = loop {
+            self.skip_separators();
+            match self.peek() {
+                Some(Token { kind: TokenKind::RBrace, span, .. }) => {
+                    let span = *span;
+                    self.next();
+                    break span;
+                }
+                None => {
+                    return Err(Error::new(Code::UnbalancedParen, "expected '}' before end of input", self.eof_span())
+                        .with_label(open, "unclosed '{'"));
+                }
+                _ => stmts.push(self.statement_or_error()),
+            }
+        };
+
+        self.depth -= 1;
+        self.nesting -= 1;
+        Ok(Expr::Block { stmts, span: Span::new(open.start, close.end) })
+    }
+
+    fn parse_if(&mut self, span: Span) -> Result<Expr, Error> {
+        let cond = self.parse_expr(0)?;
+        let then = self.parse_block()?;
+
+        let mut ahead = self.pos;
+        while matches!(self.tokens.get(ahead), Some(Token { kind: TokenKind::Newline, .. })) {
+            ahead += 1;
+        }
+        let otherwise = if matches!(self.tokens.get(ahead), Some(Token { kind: TokenKind::Else, .. })) {
+            self.pos = ahead + 1;
+            match self.peek() {
+                Some(Token { kind: TokenKind::If, span, .. }) => {
+                    let span = *span;
+                    self.next();
+                    Some(Box::new(self.parse_if(span)?))
+                }
+                _ => Some(Box::new(self.parse_block()?)),
+            }
+        } else {
+            None
+        };
+
+        Ok(Expr::If { cond: Box::new(cond), then: Box::new(then), otherwise, span })
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<(), Error> {
+        match self.next() {
+            Some(t) if std::mem::discriminant(&t.kind) == std::mem::discriminant(&kind) => Ok(()),
+            Some(Token { kind: TokenKind::Invalid(e), .. }) => Err(e),
+            Some(t) => Err(Error::new(Code::UnexpectedToken, format!("expected {}", what), t.span)),
+            None => Err(self.unexpected_end(what)),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, Error> {
+        match self.next() {
+            Some(Token { kind: TokenKind::Ident(name), .. }) => Ok(name),
+            Some(Token { kind: TokenKind::Invalid(e), .. }) => Err(e),
+            Some(t) => Err(Error::new(Code::UnexpectedToken, "expected identifier", t.span)),
+            None => Err(self.unexpected_end("identifier")),
+        }
+    }
+
+    fn unexpected_end(&self, what: &str) -> Error {
+        Error::new(Code::UnexpectedEnd, format!("expected {} before end of input", what), self.eof_span())
+    }
+
+    fn expect_assign(&mut self) -> Result<(), Error> {
+        self.expect(TokenKind::Assign, "'='")
+    }
+
+    fn parse_expr(&mut self, min_bp: u8) -> Result<Expr, Error> {
+        let nesting = self.nesting;
+        self.nest(self.peek_span())?;
+        let mut left = self.parse_prefix()?;
+
+        while let Some(Token { kind: TokenKind::Operator(op), span, .. }) = self.peek() {
+            let Some((left_bp, right_bp)) = infix_binding_power(op) else { break };
+            if left_bp < min_bp {
+                break;
+            }
+
+            let op = op.clone();
+            let span = *span;
+            self.next();
+            self.skip_newlines();
+            let right = self.parse_expr(right_bp)?;
+
+            left = Expr::Binary {
+                op,
+                span,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+
+        self.nesting = nesting;
+        Ok(left)
+    }
+
+    fn parse_prefix(&mut self) -> Result<Expr, Error> {
+        let tok = match self.next() {
+            None | Some(Token { kind: TokenKind::Newline, .. }) => {
+                if !self.at_end() {
+                    self.pos -= 1;
+                }
+                let span = if self.at_end() { self.eof_span() } else { self.peek_span() };
+                return Err(Error::new(Code::UnexpectedEnd, "expected a value before end of line", span));
+            }
+            Some(tok) => tok,
+        };
+
+        match tok.kind {
+            TokenKind::Number(value) => Ok(Expr::Number { value, span: tok.span }),
+            TokenKind::True => Ok(Expr::Bool(true)),
+            TokenKind::False => Ok(Expr::Bool(false)),
+            TokenKind::Str(s) => Ok(Expr::Str(s.into())),
+            TokenKind::Ident(name) => {
+                if !is_kind(self.peek(), &TokenKind::LParen) {
+                    return Ok(Expr::Ident { name, span: tok.span });
+                }
+                self.next();
+                let args = self.parse_args(tok.span)?;
+                Ok(Expr::Call { name, args, span: tok.span })
+            }
+
+            TokenKind::Operator(op) if op == "-" || op == "!" => {
+                let operand = self.parse_expr(PREFIX_BP)?;
+                Ok(Expr::Unary { op, span: tok.span, operand: Box::new(operand) })
+            }
+
+            TokenKind::LParen => {
+                self.skip_newlines();
+                let inner = self.parse_expr(0)?;
+                self.skip_newlines();
+                match self.next() {
+                    Some(Token { kind: TokenKind::RParen, .. }) => Ok(inner),
+                    Some(Token { kind: TokenKind::Invalid(e), .. }) => Err(e),
+                    Some(t) => Err(Error::new(Code::UnbalancedParen, "expected ')'", t.span)
+                        .with_label(tok.span, "unclosed '('")),
+                    None => Err(Error::new(Code::UnbalancedParen, "expected ')' before end of input", self.eof_span())
+                        .with_label(tok.span, "unclosed '('")),
+                }
+            }
+
+            TokenKind::LBrace => {
+                self.pos -= 1;
+                self.parse_block()
+            }
+
+            TokenKind::If => self.parse_if(tok.span),
+
+            TokenKind::RBrace if self.depth > 0 => {
+                self.pos -= 1;
+                Err(Error::new(Code::UnexpectedToken, "expected a value before '}'", tok.span))
+            }
+            TokenKind::RBrace => Err(Error::new(Code::UnbalancedParen, "unmatched '}'", tok.span)),
+            TokenKind::Invalid(e) => Err(e),
+            _ => Err(Error::new(Code::UnexpectedToken, "expected value", tok.span)),
+        }
+    }
+
+    // Arguments after the opening '(' of a call, up to and including ')'.
+    fn parse_args(&mut self, callee: Span) -> Result<Vec<Expr>, Error> {
+        let mut args = Vec::new();
+        self.skip_newlines();
+        if is_kind(self.peek(), &TokenKind::RParen) {
+            self.next();
+            return Ok(args);
+        }
+
+        loop {
+            args.push(self.parse_expr(0)?);
+            self.skip_newlines();
+            match self.next() {
+                Some(Token { kind: TokenKind::Comma, .. }) => self.skip_newlines(),
+                Some(Token { kind: TokenKind::RParen, .. }) => return Ok(args),
+                Some(Token { kind: TokenKind::Invalid(e), .. }) => return Err(e),
+                Some(t) => return Err(Error::new(Code::UnexpectedToken, "expected ',' or ')'", t.span)),
+                None => {
+                    return Err(Error::new(Code::UnbalancedParen, "expected ')' before end of input", self.eof_span())
+                        .with_label(callee, "argument list of this call is unclosed"));
+                }
+            }
+        }
+    }
+}
+
+// The tokens of one top-level statement, with spans rebased onto `text`, its
+// source. `line` is the number of the line it starts on.
+#[derive(Debug)]
+pub struct Statement {
+    pub tokens: Vec<Token>,
+    pub text: String,
+    pub line: usize,
+}
+
+// Cuts a token stream into top-level statements. A statement ends at ';' or
+// at a newline outside parentheses and blocks, unless the line ends in an
+// operator or the next line starts with `else`. Only one statement and one
+// token of lookahead are held at a time.
+pub struct Statements<R> {
+    lexer: Lexer<R>,
+    pending: Option<Token>,
+    // A read error hit while looking ahead, reported after the statement.
+    error: Option<io::Error>,
+    base: usize,
+    char_base: usize,
+    line: usize,
+}
+
+impl<R: BufRead> Statements<R> {
+    pub fn new(reader: R, first_line: usize) -> Self {
+        Self { lexer: Lexer::new(reader).with_text(), pending: None, error: None, base: 0, char_base: 0, line: first_line }
+    }
+
+    fn pull(&mut self) -> io::Result<Option<Token>> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        match self.pending.take() {
+            Some(tok) => Ok(Some(tok)),
+            None => self.lexer.next().transpose(),
+        }
+    }
+
+    pub fn next_statement(&mut self) -> io::Result<Option<Statement>> {
+        let mut tokens: Vec<Token> = Vec::new();
+        let (mut parens, mut braces) = (0usize, 0usize);
+
+        while let Some(tok) = self.pull()? {
+            let ends = parens + braces == 0
+                && match tok.kind {
+                    TokenKind::Semi => true,
+                    TokenKind::Newline => !tokens
+                        .iter()
+                        .rev()
+                        .find(|t| !matches!(t.kind, TokenKind::Newline))
+                        .is_some_and(|t| matches!(t.kind, TokenKind::Operator(_))),
+                    _ => false,
+                };
+            match tok.kind {
+                TokenKind::LParen => parens += 1,
+                TokenKind::LBrace => braces += 1,
+                TokenKind::RParen => parens = parens.saturating_sub(1),
+                TokenKind::RBrace => braces = braces.saturating_sub(1),
+                _ => {}
+            }
+            let newline = matches!(tok.kind, TokenKind::Newline);
+            tokens.push(tok);
+            if !ends {
+                continue;
+            }
+
+            if newline {
+                let next = loop {
+                    match self.pull() {
+                        Ok(Some(t)) if matches!(t.kind, TokenKind::Newline) => tokens.push(t),
+                        Ok(next) => break next,
+                        Err(e) => {
+                            self.error = Some(e);
+                            break None;
+                        }
+                    }
+                };
+                let more = next.as_ref().is_some_and(|t| matches!(t.kind, TokenKind::Else));
+                self.pending = next;
+                if more {
+                    continue;
+                }
+            }
+            break;
+        }
+
+        let Some(last) = tokens.last() else { return Ok(None) };
+        let (end, char_end) = (last.span.end, last.char_span.end);
+        let text = self.lexer.drain_text(end);
+        for tok in &mut tokens {
+            tok.span = Span::new(tok.span.start - self.base, tok.span.end - self.base);
+            tok.char_span = Span::new(tok.char_span.start - self.char_base, tok.char_span.end - self.char_base);
+            if let TokenKind::Invalid(e) = &mut tok.kind {
+                e.span = Span::new(e.span.start - self.base, e.span.end - self.base);
+            }
+        }
+        let line = self.line;
+        self.base = end;
+        self.char_base = char_end;
+        self.line += text.matches('\n').count();
+        Ok(Some(Statement { tokens, text, line }))
+    }
+}
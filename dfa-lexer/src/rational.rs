@@ -0,0 +1,118 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::bigint::BigInt;
+
+// Exact fraction kept in lowest terms with a positive denominator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rational {
+    num: BigInt,
+    den: BigInt,
+}
+
+impl Rational {
+    pub fn new(num: BigInt, den: BigInt) -> Option<Self> {
+        if den.is_zero() {
+            return None;
+        }
+        let g = num.gcd(&den);
+        let (mut num, mut den) = (num.div_rem(&g)?.0, den.div_rem(&g)?.0);
+        if den.is_negative() {
+            num = -&num;
+            den = -&den;
+        }
+        Some(Self { num, den })
+    }
+
+    pub fn from_big(n: BigInt) -> Self {
+        Self { num: n, den: BigInt::from_i64(1) }
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.num
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.den
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.den == BigInt::from_i64(1)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.num.to_f64() / self.den.to_f64()
+    }
+
+    pub fn abs(&self) -> Self {
+        Self { num: self.num.abs(), den: self.den.clone() }
+    }
+
+    pub fn recip(&self) -> Option<Self> {
+        Self::new(self.den.clone(), self.num.clone())
+    }
+
+    pub fn checked_div(&self, other: &Self) -> Option<Self> {
+        Self::new(&self.num * &other.den, &self.den * &other.num)
+    }
+
+    pub fn pow(&self, exp: u64) -> Option<Self> {
+        Some(Self { num: self.num.pow(exp)?, den: self.den.pow(exp)? })
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.num * &other.den).cmp(&(&other.num * &self.den))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, other: &Rational) -> Rational {
+        let num = &(&self.num * &other.den) + &(&other.num * &self.den);
+        Rational::new(num, &self.den * &other.den).unwrap()
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, other: &Rational) -> Rational {
+        self + &-other
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, other: &Rational) -> Rational {
+        Rational::new(&self.num * &other.num, &self.den * &other.den).unwrap()
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational { num: -&self.num, den: self.den.clone() }
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
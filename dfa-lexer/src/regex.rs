@@ -0,0 +1,234 @@
+// Just enough regular expression syntax to describe tokens:
+// literals, escapes, `.`, `[a-z]` / `[^...]` classes, grouping, `|`, `*`, `+`, `?`,
+// and `\p{XID_Start}` / `\p{XID_Continue}` inside or outside classes.
+
+use crate::unicode;
+
+pub type CharSet = Vec<(char, char)>;
+
+#[derive(Debug, Clone)]
+pub enum Regex {
+    Empty,
+    Set(CharSet),
+    Concat(Vec<Regex>),
+    Alt(Vec<Regex>),
+    Star(Box<Regex>),
+    Plus(Box<Regex>),
+    Optional(Box<Regex>),
+}
+
+pub fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        char::MAX => None,
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+pub fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        '\0' => None,
+        _ => char::from_u32(c as u32 - 1),
+    }
+}
+
+pub fn normalize(mut set: CharSet) -> CharSet {
+    set.sort();
+    let mut out: CharSet = Vec::new();
+    for (lo, hi) in set {
+        if let Some(last) = out.last_mut()
+            && next_char(last.1).is_none_or(|n| lo <= n)
+        {
+            last.1 = last.1.max(hi);
+            continue;
+        }
+        out.push((lo, hi));
+    }
+    out
+}
+
+pub fn complement(set: &CharSet) -> CharSet {
+    let mut out = Vec::new();
+    let mut from = Some('\0');
+    for &(lo, hi) in set {
+        if let Some(start) = from
+            && start < lo
+        {
+            out.push((start, prev_char(lo).unwrap()));
+        }
+        from = next_char(hi);
+    }
+    if let Some(start) = from {
+        out.push((start, char::MAX));
+    }
+    out
+}
+
+pub fn contains(set: &CharSet, c: char) -> bool {
+    let i = set.partition_point(|r| r.0 <= c);
+    i > 0 && c <= set[i - 1].1
+}
+
+impl Regex {
+    pub fn parse(pattern: &str) -> Result<Regex, String> {
+        let mut p = RegexParser { chars: pattern.chars().collect(), pos: 0 };
+        let re = p.alternation()?;
+        if p.pos < p.chars.len() {
+            return Err(format!("unexpected '{}' at offset {}", p.chars[p.pos], p.pos));
+        }
+        Ok(re)
+    }
+}
+
+struct RegexParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl RegexParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn alternation(&mut self) -> Result<Regex, String> {
+        let mut branches = vec![self.concatenation()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.concatenation()?);
+        }
+        Ok(if branches.len() == 1 { branches.pop().unwrap() } else { Regex::Alt(branches) })
+    }
+
+    fn concatenation(&mut self) -> Result<Regex, String> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            items.push(self.repetition()?);
+        }
+        Ok(match items.len() {
+            0 => Regex::Empty,
+            1 => items.pop().unwrap(),
+            _ => Regex::Concat(items),
+        })
+    }
+
+    fn repetition(&mut self) -> Result<Regex, String> {
+        let mut re = self.atom()?;
+        loop {
+            re = match self.peek() {
+                Some('*') => Regex::Star(Box::new(re)),
+                Some('+') => Regex::Plus(Box::new(re)),
+                Some('?') => Regex::Optional(Box::new(re)),
+                _ => return Ok(re),
+            };
+            self.pos += 1;
+        }
+    }
+
+    fn atom(&mut self) -> Result<Regex, String> {
+        let at = self.pos;
+        match self.bump() {
+            Some('(') => {
+                let re = self.alternation()?;
+                if self.bump() != Some(')') {
+                    return Err(format!("unclosed group at offset {}", at));
+                }
+                Ok(re)
+            }
+            Some('[') => self.class(at),
+            Some('.') => Ok(Regex::Set(complement(&vec![('\n', '\n')]))),
+            Some('\\') if self.peek() == Some('p') => Ok(Regex::Set(self.property()?)),
+            Some('\\') => {
+                let c = self.escape()?;
+                Ok(Regex::Set(vec![(c, c)]))
+            }
+            Some(c @ ('*' | '+' | '?')) => Err(format!("nothing to repeat before '{}' at offset {}", c, at)),
+            Some(c) => Ok(Regex::Set(vec![(c, c)])),
+            None => Err("unexpected end of pattern".into()),
+        }
+    }
+
+    fn class(&mut self, at: usize) -> Result<Regex, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut set = Vec::new();
+        let mut first = true;
+        loop {
+            let lo = match self.bump() {
+                None => return Err(format!("unclosed class at offset {}", at)),
+                Some(']') if !first => break,
+                Some('\\') if self.peek() == Some('p') => {
+                    set.extend(self.property()?);
+                    first = false;
+                    continue;
+                }
+                Some('\\') => self.escape()?,
+                Some(c) => c,
+            };
+            first = false;
+
+            let hi = if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                match self.bump() {
+                    Some('\\') => self.escape()?,
+                    Some(c) => c,
+                    None => return Err(format!("unclosed class at offset {}", at)),
+                }
+            } else {
+                lo
+            };
+
+            if hi < lo {
+                return Err(format!("invalid range '{}-{}' at offset {}", lo, hi, at));
+            }
+            set.push((lo, hi));
+        }
+
+        let set = normalize(set);
+        Ok(Regex::Set(if negated { complement(&set) } else { set }))
+    }
+
+    // `\p{Name}`, with the backslash already consumed.
+    fn property(&mut self) -> Result<CharSet, String> {
+        let at = self.pos - 1;
+        self.pos += 1;
+        if self.bump() != Some('{') {
+            return Err(format!("expected '{{' after '\\p' at offset {}", at));
+        }
+        let mut name = String::new();
+        loop {
+            match self.bump() {
+                Some('}') => break,
+                Some(c) => name.push(c),
+                None => return Err(format!("unclosed property at offset {}", at)),
+            }
+        }
+        match unicode::property(&name) {
+            Some(set) => Ok(set),
+            None => Err(format!("unknown property '{}' at offset {}", name, at)),
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, String> {
+        match self.bump() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some(c) => Ok(c),
+            None => Err("dangling '\\' at end of pattern".into()),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use crate::regex::Regex;
+
+// A token spec is one rule per line: `NAME = pattern`. Blank lines and lines
+// starting with `#` are ignored. Earlier rules win ties on match length.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub regex: Regex,
+}
+
+pub fn parse(src: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+
+    for (i, line) in src.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let (name, pattern) = trimmed
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `NAME = pattern`", i + 1))?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("line {}: invalid rule name '{}'", i + 1, name));
+        }
+
+        let regex = Regex::parse(pattern.trim()).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        rules.push(Rule { name: name.to_string(), regex });
+    }
+
+    if rules.is_empty() {
+        return Err("spec defines no rules".into());
+    }
+    Ok(rules)
+}
@@ -0,0 +1,247 @@
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+
+use dfa_lexer::unicode;
+
+const HISTORY_FILE: &str = ".dfa_lexer_history";
+const HISTORY_LIMIT: usize = 1000;
+
+// Raw mode on the Linux targets whose termios layout the libc shim knows.
+// Everywhere else, and when stdin is not a terminal, lines are read cooked.
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod raw {
+    use std::mem::MaybeUninit;
+
+    use crate::libc;
+
+    pub struct RawMode(libc::termios);
+
+    impl RawMode {
+        pub fn enable() -> Option<RawMode> {
+            let mut saved = MaybeUninit::<libc::termios>::uninit();
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, saved.as_mut_ptr()) } != 0 {
+                return None;
+            }
+            let saved = unsafe { saved.assume_init() };
+
+            let mut raw = saved;
+            raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+            raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::ISIG | libc::IEXTEN);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+                return None;
+            }
+            Some(RawMode(saved))
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.0) };
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+mod raw {
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Option<RawMode> {
+            None
+        }
+    }
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Interrupt,
+    Eof,
+    Other,
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut b = [0u8];
+    Ok(match input.read(&mut b)? {
+        0 => None,
+        _ => Some(b[0]),
+    })
+}
+
+fn read_key(input: &mut impl Read) -> io::Result<Key> {
+    let Some(b) = read_byte(input)? else { return Ok(Key::Eof) };
+
+    Ok(match b {
+        b'\r' | b'\n' => Key::Enter,
+        127 | 8 => Key::Backspace,
+        1 => Key::Home,
+        5 => Key::End,
+        3 => Key::Interrupt,
+        4 => Key::Eof,
+        0x1b => match (read_byte(input)?, read_byte(input)?) {
+            (Some(b'['), Some(b'A')) => Key::Up,
+            (Some(b'['), Some(b'B')) => Key::Down,
+            (Some(b'['), Some(b'C')) => Key::Right,
+            (Some(b'['), Some(b'D')) => Key::Left,
+            (Some(b'[' | b'O'), Some(b'H')) => Key::Home,
+            (Some(b'[' | b'O'), Some(b'F')) => Key::End,
+            (Some(b'['), Some(b'3')) => {
+                read_byte(input)?;
+                Key::Delete
+            }
+            _ => Key::Other,
+        },
+        _ if b < 0x20 => Key::Other,
+        _ => {
+            // Leading byte of a UTF-8 sequence: pull in its continuation bytes.
+            let len = match b {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                0xc0.. => 2,
+                _ => 1,
+            };
+            let mut bytes = vec![b];
+            for _ in 1..len {
+                bytes.extend(read_byte(input)?);
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Other,
+            }
+        }
+    })
+}
+
+// A minimal line editor: cursor movement, history recall and a history file
+// under $HOME that survives between sessions.
+pub struct Editor {
+    history: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        let path = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        let history = path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .map(|s| s.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        Self { history, path }
+    }
+
+    pub fn add_history(&mut self, line: &str) {
+        let line = line.trim_end();
+        if line.is_empty() || self.history.last().is_some_and(|l| l == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+    }
+
+    pub fn save(&self) {
+        if let Some(path) = &self.path {
+            let mut text = self.history.join("\n");
+            text.push('\n');
+            let _ = fs::write(path, text);
+        }
+    }
+
+    // Ok(None) at end of input; Ctrl-C gives an `Interrupted` error so the
+    // caller can drop a half-typed multi-line input.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let raw = if crate::stdin_is_tty() { raw::RawMode::enable() } else { None };
+        match raw {
+            Some(_guard) => self.edit(prompt),
+            None => {
+                print!("{}", prompt);
+                io::stdout().flush()?;
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+            }
+        }
+    }
+
+    fn edit(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut stdin = io::stdin().lock();
+        let mut out = io::stdout().lock();
+        let mut buf: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Index into history while browsing; history.len() is the line being typed.
+        let mut recall = self.history.len();
+        let mut typed: Vec<char> = Vec::new();
+
+        loop {
+            let shown: String = buf.iter().collect();
+            let col = unicode::str_width(prompt) + buf[..cursor].iter().copied().map(unicode::width).sum::<usize>();
+            write!(out, "\r{}{}\x1b[K\r", prompt, shown)?;
+            if col > 0 {
+                write!(out, "\x1b[{}C", col)?;
+            }
+            out.flush()?;
+
+            match read_key(&mut stdin)? {
+                Key::Char(c) => {
+                    buf.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    return Ok(Some(buf.into_iter().collect()));
+                }
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    buf.remove(cursor);
+                }
+                Key::Delete if cursor < buf.len() => {
+                    buf.remove(cursor);
+                }
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(buf.len()),
+                Key::Home => cursor = 0,
+                Key::End => cursor = buf.len(),
+                Key::Up if recall > 0 => {
+                    if recall == self.history.len() {
+                        typed = buf.clone();
+                    }
+                    recall -= 1;
+                    buf = self.history[recall].chars().collect();
+                    cursor = buf.len();
+                }
+                Key::Down if recall < self.history.len() => {
+                    recall += 1;
+                    buf = match self.history.get(recall) {
+                        Some(line) => line.chars().collect(),
+                        None => typed.clone(),
+                    };
+                    cursor = buf.len();
+                }
+                Key::Interrupt => {
+                    write!(out, "^C\r\n")?;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Key::Eof if buf.is_empty() => {
+                    write!(out, "\r\n")?;
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+// The Unicode properties the lexer and diagnostics need. Identifiers use
+// the real XID tables; widths and marks are approximated by block.
+mod tables;
+
+use std::cmp::Ordering;
+
+use crate::regex::CharSet;
+
+// Nonspacing and enclosing marks in the blocks that hold nearly all of them.
+// They continue identifiers and take no columns on screen.
+const COMBINING: &[(char, char)] = &[
+    ('\u{0300}', '\u{036F}'),
+    ('\u{0483}', '\u{0489}'),
+    ('\u{0591}', '\u{05BD}'),
+    ('\u{0610}', '\u{061A}'),
+    ('\u{064B}', '\u{065F}'),
+    ('\u{0900}', '\u{0903}'),
+    ('\u{093A}', '\u{094F}'),
+    ('\u{0951}', '\u{0957}'),
+    ('\u{1AB0}', '\u{1AFF}'),
+    ('\u{1DC0}', '\u{1DFF}'),
+    ('\u{200C}', '\u{200D}'),
+    ('\u{20D0}', '\u{20FF}'),
+    ('\u{FE00}', '\u{FE0F}'),
+    ('\u{FE20}', '\u{FE2F}'),
+];
+
+// East Asian Wide and Fullwidth blocks, plus the emoji planes.
+const WIDE: &[(char, char)] = &[
+    ('\u{1100}', '\u{115F}'),
+    ('\u{2E80}', '\u{303E}'),
+    ('\u{3041}', '\u{33FF}'),
+    ('\u{3400}', '\u{4DBF}'),
+    ('\u{4E00}', '\u{9FFF}'),
+    ('\u{A000}', '\u{A4CF}'),
+    ('\u{AC00}', '\u{D7A3}'),
+    ('\u{F900}', '\u{FAFF}'),
+    ('\u{FE30}', '\u{FE4F}'),
+    ('\u{FF00}', '\u{FF60}'),
+    ('\u{FFE0}', '\u{FFE6}'),
+    ('\u{1F300}', '\u{1F64F}'),
+    ('\u{1F900}', '\u{1F9FF}'),
+    ('\u{20000}', '\u{2FFFD}'),
+    ('\u{30000}', '\u{3FFFD}'),
+];
+
+fn within(ranges: &[(char, char)], c: char) -> bool {
+    ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi)
+}
+
+pub fn is_combining(c: char) -> bool {
+    within(COMBINING, c)
+}
+
+fn in_table(ranges: &[(char, char)], c: char) -> bool {
+    ranges.binary_search_by(|&(lo, hi)| if hi < c { Ordering::Less } else if lo > c { Ordering::Greater } else { Ordering::Equal }).is_ok()
+}
+
+// XID_Start without '_', which token specs add themselves.
+pub fn is_xid_start(c: char) -> bool {
+    in_table(tables::XID_START, c)
+}
+
+pub fn is_xid_continue(c: char) -> bool {
+    in_table(tables::XID_CONTINUE, c)
+}
+
+// Terminal columns taken by `c`: 0 for marks and controls, 2 for wide chars.
+pub fn width(c: char) -> usize {
+    if c.is_control() || is_combining(c) || c == '\u{200B}' {
+        0
+    } else if within(WIDE, c) {
+        2
+    } else {
+        1
+    }
+}
+
+pub fn str_width(s: &str) -> usize {
+    s.chars().map(width).sum()
+}
+
+// The sets behind the regex escapes `\p{XID_Start}` and `\p{XID_Continue}`.
+pub fn property(name: &str) -> Option<CharSet> {
+    match name {
+        "XID_Start" => Some(tables::XID_START.to_vec()),
+        "XID_Continue" => Some(tables::XID_CONTINUE.to_vec()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers_follow_xid() {
+        for c in ['a', 'Z', 'é', 'ß', 'ª', '名', 'Ω', 'Ⅻ'] {
+            assert!(is_xid_start(c) && is_xid_continue(c), "{:?}", c);
+        }
+        for c in ['1', '_', '\u{301}', '٣'] {
+            assert!(!is_xid_start(c) && is_xid_continue(c), "{:?}", c);
+        }
+        // Alphanumeric to `char`, but not identifier characters.
+        for c in ['²', '½', '①', 'ⓐ', '\u{2E2F}', '$', ' '] {
+            assert!(!is_xid_start(c) && !is_xid_continue(c), "{:?}", c);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::diag::{Code, Error};
+use crate::rational::Rational;
+
+// What `/` produces when two integers do not divide evenly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Numeric {
+    #[default]
+    Int,
+    Rational,
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mode {
+    pub numeric: Numeric,
+    pub bigint: bool,
+}
+
+// Integers stay in i64 until they overflow; only --bigint mode lets them
+// grow into a BigInt, which shrinks back once it fits again. Rationals only
+// appear in --rational mode and collapse to integers when the denominator is 1.
+// `Unit` is what statements, loops and an `if` without `else` produce.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Big(BigInt),
+    Ratio(Rational),
+    Float(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    Unit,
+}
+
+pub fn overflow(what: &str) -> Error {
+    Error::bare(Code::Overflow, format!("integer overflow in {}", what))
+        .with_help("run with --bigint for arbitrary precision")
+}
+
+fn division_by_zero() -> Error {
+    Error::bare(Code::DivisionByZero, "division by zero")
+}
+
+fn unknown_operator(op: &str) -> Error {
+    Error::bare(Code::InvalidArgument, format!("unknown operator '{}'", op))
+}
+
+pub fn type_mismatch(msg: impl Into<String>) -> Error {
+    Error::bare(Code::TypeMismatch, msg)
+}
+
+fn too_large(what: &str) -> Error {
+    Error::bare(Code::Overflow, format!("{} result is too large", what))
+}
+
+// Without --bigint the result has to fit in an i64, so a base of magnitude at least 2 (or
+// a fraction) raised that high is refused before the power is multiplied out.
+fn pow_ratio(base: &Rational, exp: u64, mode: Mode) -> Result<Value, Error> {
+    let bits = base.numer().bits().max(base.denom().bits());
+    if !mode.bigint && bits.saturating_sub(1).saturating_mul(exp) >= 64 {
+        return Err(overflow("pow"));
+    }
+    let r = base.pow(exp).ok_or_else(|| too_large("pow"))?;
+    Value::from_ratio(r).check(mode, "pow")
+}
+
+impl Value {
+    pub fn from_big(b: BigInt) -> Value {
+        b.to_i64().map_or(Value::Big(b), Value::Int)
+    }
+
+    pub fn from_ratio(r: Rational) -> Value {
+        if r.is_integer() {
+            Value::from_big(r.numer().clone())
+        } else {
+            Value::Ratio(r)
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::Big(_) => "int",
+            Value::Ratio(_) => "rational",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "str",
+            Value::Unit => "()",
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        !matches!(self, Value::Bool(_) | Value::Str(_) | Value::Unit)
+    }
+
+    fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(n) => Some(BigInt::from_i64(*n)),
+            Value::Big(b) => Some(b.clone()),
+            _ => None,
+        }
+    }
+
+    fn to_ratio(&self) -> Option<Rational> {
+        match self {
+            Value::Ratio(r) => Some(r.clone()),
+            Value::Float(_) | Value::Bool(_) | Value::Str(_) | Value::Unit => None,
+            _ => self.to_big().map(Rational::from_big),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Int(n) => *n as f64,
+            Value::Big(b) => b.to_f64(),
+            Value::Ratio(r) => r.to_f64(),
+            Value::Float(f) => *f,
+            Value::Bool(_) | Value::Str(_) | Value::Unit => f64::NAN,
+        }
+    }
+
+    // Outside --bigint mode every integer, including both halves of a
+    // rational, has to fit in 64 bits.
+    fn check(self, mode: Mode, what: &str) -> Result<Value, Error> {
+        let fits = match &self {
+            Value::Big(_) => false,
+            Value::Ratio(r) => r.numer().to_i64().is_some() && r.denom().to_i64().is_some(),
+            _ => true,
+        };
+        if fits || mode.bigint { Ok(self) } else { Err(overflow(what)) }
+    }
+
+    pub fn negate(&self, mode: Mode) -> Result<Value, Error> {
+        match self {
+            Value::Int(n) => match n.checked_neg() {
+                Some(r) => Ok(Value::Int(r)),
+                None => Value::from_big(-&BigInt::from_i64(*n)).check(mode, "'-'"),
+            },
+            Value::Big(b) => Value::from_big(-b).check(mode, "'-'"),
+            Value::Ratio(r) => Value::from_ratio(-r).check(mode, "'-'"),
+            Value::Float(f) => Ok(Value::Float(-f)),
+            v => Err(type_mismatch(format!("cannot negate {}", v.type_name()))),
+        }
+    }
+
+    pub fn abs(&self, mode: Mode) -> Result<Value, Error> {
+        match self {
+            Value::Int(n) if *n < 0 => self.negate(mode).map_err(|_| overflow("abs")),
+            Value::Big(b) => Ok(Value::from_big(b.abs())),
+            Value::Ratio(r) => Ok(Value::Ratio(r.abs())),
+            Value::Float(f) => Ok(Value::Float(f.abs())),
+            v => Ok(v.clone()),
+        }
+    }
+
+    pub fn pow(&self, exp: &Value, mode: Mode) -> Result<Value, Error> {
+        let e = match exp {
+            Value::Int(e) if !matches!(self, Value::Float(_)) => *e,
+            Value::Big(_) if !matches!(self, Value::Float(_)) => {
+                return Err(Error::bare(Code::InvalidArgument, "pow exponent is too large"));
+            }
+            _ => return Ok(Value::Float(self.to_f64().powf(exp.to_f64()))),
+        };
+
+        if e < 0 {
+            return match mode.numeric {
+                Numeric::Int => Err(Error::bare(Code::InvalidArgument, "pow exponent must not be negative")
+                    .with_help("run with --rational or --float to allow negative exponents")),
+                Numeric::Float => Ok(Value::Float(self.to_f64().powf(e as f64))),
+                Numeric::Rational => pow_ratio(&self.to_ratio().unwrap().recip().ok_or_else(division_by_zero)?, e.unsigned_abs(), mode),
+            };
+        }
+
+        if let Value::Int(base) = self
+            && let Some(v) = u32::try_from(e).ok().and_then(|e| base.checked_pow(e))
+        {
+            return Ok(Value::Int(v));
+        }
+        pow_ratio(&self.to_ratio().unwrap(), e as u64, mode)
+    }
+
+    pub fn gcd(&self, other: &Value, mode: Mode) -> Result<Value, Error> {
+        match (self.to_big(), other.to_big()) {
+            (Some(a), Some(b)) => Value::from_big(a.gcd(&b)).check(mode, "gcd"),
+            _ => Err(Error::bare(Code::InvalidArgument, "gcd expects integers")),
+        }
+    }
+}
+
+fn float_arith(op: &str, a: f64, b: f64) -> Result<Value, Error> {
+    Ok(Value::Float(match op {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" if b == 0.0 => return Err(division_by_zero()),
+        "/" => a / b,
+        _ => return Err(unknown_operator(op)),
+    }))
+}
+
+fn arith(op: &str, l: &Value, r: &Value, mode: Mode) -> Result<Value, Error> {
+    let what = format!("'{}'", op);
+
+    if let (Value::Int(a), Value::Int(b)) = (l, r) {
+        let exact = op != "/" || *b == 0 || a.checked_rem(*b).is_none_or(|m| m == 0);
+        if exact || mode.numeric == Numeric::Int {
+            let checked = match op {
+                "+" => a.checked_add(*b),
+                "-" => a.checked_sub(*b),
+                "*" => a.checked_mul(*b),
+                "/" if *b == 0 => return Err(division_by_zero()),
+                "/" => a.checked_div(*b),
+                _ => return Err(unknown_operator(op)),
+            };
+            match checked {
+                Some(v) => return Ok(Value::Int(v)),
+                None if !mode.bigint => return Err(overflow(&what)),
+                None => {}
+            }
+        }
+    }
+
+    let (Some(a), Some(b)) = (l.to_ratio(), r.to_ratio()) else {
+        return float_arith(op, l.to_f64(), r.to_f64());
+    };
+
+    let v = match op {
+        "+" => &a + &b,
+        "-" => &a - &b,
+        "*" => &a * &b,
+        "/" => {
+            let q = a.checked_div(&b).ok_or_else(division_by_zero)?;
+            match mode.numeric {
+                _ if q.is_integer() => q,
+                Numeric::Int => {
+                    let t = a.numer().div_rem(b.numer()).unwrap().0;
+                    Rational::from_big(t)
+                }
+                Numeric::Float => return Ok(Value::Float(q.to_f64())),
+                Numeric::Rational => q,
+            }
+        }
+        _ => return Err(unknown_operator(op)),
+    };
+    Value::from_ratio(v).check(mode, &what)
+}
+
+// Arithmetic needs numbers on both sides, except that `+` joins two
+// strings. Ordering compares two numbers or two strings; `==` and `!=`
+// accept any two values and are simply false across types.
+pub fn binary(op: &str, l: &Value, r: &Value, mode: Mode) -> Result<Value, Error> {
+    if let "==" | "!=" = op {
+        return Ok(Value::Bool((l == r) == (op == "==")));
+    }
+    let strings = matches!((l, r), (Value::Str(_), Value::Str(_)));
+    if strings && op == "+" {
+        return Ok(Value::Str(format!("{}{}", l, r).into()));
+    }
+    let ordering = matches!(op, "<" | "<=" | ">" | ">=");
+    if !(l.is_numeric() && r.is_numeric() || strings && ordering) {
+        let msg = format!("cannot apply '{}' to {} and {}", op, l.type_name(), r.type_name());
+        let err = type_mismatch(msg);
+        if op == "+" && (matches!(l, Value::Str(_)) || matches!(r, Value::Str(_))) {
+            return Err(err.with_help("print takes several arguments: print(\"total:\", n)"));
+        }
+        return Err(err);
+    }
+
+    let ord = match op {
+        "<" | "<=" | ">" | ">=" => l.partial_cmp(r),
+        _ => return arith(op, l, r, mode),
+    };
+    Ok(Value::Bool(match (op, ord) {
+        (_, None) => false,
+        ("<", Some(o)) => o.is_lt(),
+        ("<=", Some(o)) => o.is_le(),
+        (">", Some(o)) => o.is_gt(),
+        (_, Some(o)) => o.is_ge(),
+    }))
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            (Value::Unit, Value::Unit) => Some(Ordering::Equal),
+            _ if !self.is_numeric() || !other.is_numeric() => None,
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            _ => match (self.to_ratio(), other.to_ratio()) {
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => self.to_f64().partial_cmp(&other.to_f64()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Big(b) => write!(f, "{}", b),
+            Value::Ratio(r) => write!(f, "{}", r),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Unit => write!(f, "()"),
+        }
+    }
+}
@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::diag::{Error, Span};
+use crate::value::{self, Value};
+use crate::eval::{self, Callee, Env, Function};
+use crate::parser::Expr;
+
+// A call resolves its callee with `Callee` before the arguments are
+// evaluated, then `Call` pops them. Jump targets are absolute instruction indices. `what` in the bool checks
+// names the operand for the type error, as the tree walker does.
+//
+// Names declared inside a block or a function live in slots of the running
+// frame; `Load`, `Define` and `Assign` go to the globals by name. An
+// assignment to a name with no visible binding updates the global if there
+// is one and otherwise declares a local, which only the run can tell, so
+// `AssignMaybe` and `LoadMaybe` take both a slot and a name.
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Const(u32),
+    BigConst(u32),
+    Bool(bool),
+    Unit,
+    Pop,
+    Load(u32),
+    Define(u32),
+    Assign(u32),
+    LoadLocal(u32),
+    StoreLocal(u32),
+    LoadMaybe(u32, u32),
+    AssignMaybe(u32, u32),
+    ClearLocals(u32, u32),
+    DefineFn(u32),
+    Neg,
+    Not,
+    Binary(&'static str),
+    CheckBool(&'static str),
+    Jump(u32),
+    JumpIfFalse(u32, &'static str),
+    Callee(u32, u32),
+    Call(u32, u32),
+    Return,
+    Fail,
+}
+
+// One compiled statement or function body. `spans[i]` is the source span
+// reported when `code[i]` fails; `slots` is how many locals a frame needs.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    code: Vec<Op>,
+    spans: Vec<Span>,
+    consts: Vec<Value>,
+    names: Vec<String>,
+    functions: Vec<(String, Rc<Function>)>,
+    slots: usize,
+}
+
+const BINARY_OPS: &[&str] = &["+", "-", "*", "/", "<", "<=", ">", ">=", "==", "!="];
+
+// `maybe` marks a local that `AssignMaybe` may have left to the global.
+struct Local {
+    name: String,
+    slot: u32,
+    maybe: bool,
+}
+
+struct Compiler {
+    chunk: Chunk,
+    names: HashMap<String, u32>,
+    // Open scopes, innermost last. Empty at the top level of a statement,
+    // where every name is a global.
+    scopes: Vec<Vec<Local>>,
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.spans.push(span);
+        self.chunk.code.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        self.chunk.code.len() as u32
+    }
+
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.chunk.code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t, _) => *t = target,
+            op => unreachable!("patching {:?}", op),
+        }
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        if let Some(&i) = self.names.get(name) {
+            return i;
+        }
+        let i = self.chunk.names.len() as u32;
+        self.chunk.names.push(name.to_string());
+        self.names.insert(name.to_string(), i);
+        i
+    }
+
+    fn resolve(&self, name: &str) -> Option<&Local> {
+        self.scopes.iter().rev().flatten().find(|l| l.name == name)
+    }
+
+    // Declares `name` in the innermost scope, reusing its slot if the scope
+    // already has one; a `let` makes it certain.
+    fn declare(&mut self, name: &str, maybe: bool) -> u32 {
+        let next = self.scopes.iter().map(Vec::len).sum::<usize>() as u32;
+        let scope = self.scopes.last_mut().unwrap();
+        if let Some(local) = scope.iter_mut().find(|l| l.name == name) {
+            local.maybe &= maybe;
+            return local.slot;
+        }
+        scope.push(Local { name: name.to_string(), slot: next, maybe });
+        self.chunk.slots = self.chunk.slots.max(next as usize + 1);
+        next
+    }
+
+    fn open_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    // Clears the scope's slots so a later block, or the next loop
+    // iteration, starts without them.
+    fn close_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        if let Some(first) = scope.first() {
+            self.emit(Op::ClearLocals(first.slot, scope.len() as u32), Span::default());
+        }
+    }
+
+    // Applies `op` to the value on top of the stack and `right`.
+    fn operator(&mut self, op: &str, span: Span, right: &Expr) {
+        match op {
+            "&&" => {
+                let short = self.emit(Op::JumpIfFalse(0, "left operand of '&&'"), span);
+                self.expr(right);
+                self.emit(Op::CheckBool("right operand of '&&'"), span);
+                let end = self.emit(Op::Jump(0), span);
+                self.patch(short);
+                self.emit(Op::Bool(false), span);
+                self.patch(end);
+            }
+            "||" => {
+                let long = self.emit(Op::JumpIfFalse(0, "left operand of '||'"), span);
+                self.emit(Op::Bool(true), span);
+                let end = self.emit(Op::Jump(0), span);
+                self.patch(long);
+                self.expr(right);
+                self.emit(Op::CheckBool("right operand of '||'"), span);
+                self.patch(end);
+            }
+            _ => {
+                self.expr(right);
+                let op = BINARY_OPS.iter().find(|o| **o == op).expect("parser only produces known operators");
+                self.emit(Op::Binary(op), span);
+            }
+        }
+    }
+
+    // Every expression leaves exactly one value on the stack.
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number { value, span } => {
+                let i = self.chunk.consts.len() as u32;
+                self.chunk.consts.push(value.clone());
+                let op = if let Value::Big(_) = value { Op::BigConst(i) } else { Op::Const(i) };
+                self.emit(op, *span);
+            }
+
+            Expr::Bool(b) => {
+                self.emit(Op::Bool(*b), Span::default());
+            }
+
+            Expr::Str(s) => {
+                let i = self.chunk.consts.len() as u32;
+                self.chunk.consts.push(Value::Str(s.clone()));
+                self.emit(Op::Const(i), Span::default());
+            }
+
+            Expr::Ident { name, span } => {
+                let op = match self.resolve(name) {
+                    Some(Local { slot, maybe: false, .. }) => Op::LoadLocal(*slot),
+                    Some(Local { slot, .. }) => {
+                        let slot = *slot;
+                        Op::LoadMaybe(slot, self.name(name))
+                    }
+                    None => Op::Load(self.name(name)),
+                };
+                self.emit(op, *span);
+            }
+
+            Expr::Let { name, value, .. } => {
+                self.expr(value);
+                let op = if self.scopes.is_empty() { Op::Define(self.name(name)) } else { Op::StoreLocal(self.declare(name, false)) };
+                self.emit(op, Span::default());
+            }
+
+            Expr::Assign { name, value, .. } => {
+                self.expr(value);
+                let op = match self.resolve(name) {
+                    _ if self.scopes.is_empty() => Op::Assign(self.name(name)),
+                    Some(Local { slot, maybe: false, .. }) => Op::StoreLocal(*slot),
+                    Some(Local { slot, .. }) => {
+                        let slot = *slot;
+                        Op::AssignMaybe(slot, self.name(name))
+                    }
+                    None => {
+                        let slot = self.declare(name, true);
+                        Op::AssignMaybe(slot, self.name(name))
+                    }
+                };
+                self.emit(op, Span::default());
+            }
+
+            Expr::FnDef { name, params, body, span } => {
+                let f = Function::new(params.clone(), body.clone());
+                let i = self.chunk.functions.len() as u32;
+                self.chunk.functions.push((name.clone(), Rc::new(f)));
+                self.emit(Op::DefineFn(i), *span);
+                self.emit(Op::Unit, *span);
+            }
+
+            Expr::Call { name, args, span } => {
+                let n = self.name(name);
+                self.emit(Op::Callee(n, args.len() as u32), *span);
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(Op::Call(n, args.len() as u32), *span);
+            }
+
+            Expr::Unary { op, span, operand } => {
+                self.expr(operand);
+                self.emit(if op == "!" { Op::Not } else { Op::Neg }, *span);
+            }
+
+            Expr::Binary { .. } => {
+                let (first, rest) = expr.chain();
+                self.expr(first);
+                for (op, span, right) in rest {
+                    self.operator(op, span, right);
+                }
+            }
+
+            Expr::Error { span } => {
+                self.emit(Op::Fail, *span);
+            }
+
+            Expr::Block { stmts, .. } => {
+                self.open_scope();
+                for (i, stmt) in stmts.iter().enumerate() {
+                    self.expr(stmt);
+                    if i + 1 < stmts.len() || stmt.is_statement() {
+                        self.emit(Op::Pop, Span::default());
+                    }
+                }
+                if stmts.last().is_none_or(Expr::is_statement) {
+                    self.emit(Op::Unit, Span::default());
+                }
+                self.close_scope();
+            }
+
+            Expr::If { cond, then, otherwise, span } => {
+                self.expr(cond);
+                let skip = self.emit(Op::JumpIfFalse(0, "condition of 'if'"), *span);
+                self.expr(then);
+                let end = self.emit(Op::Jump(0), *span);
+                self.patch(skip);
+                match otherwise {
+                    Some(e) => self.expr(e),
+                    None => {
+                        self.emit(Op::Unit, *span);
+                    }
+                }
+                self.patch(end);
+            }
+
+            Expr::While { cond, body, span } => {
+                let top = self.here();
+                self.expr(cond);
+                let exit = self.emit(Op::JumpIfFalse(0, "condition of 'while'"), *span);
+                self.expr(body);
+                self.emit(Op::Pop, *span);
+                self.emit(Op::Jump(top), *span);
+                self.patch(exit);
+                self.emit(Op::Unit, *span);
+            }
+        }
+    }
+
+    fn finish(mut self, body: &Expr) -> Chunk {
+        self.expr(body);
+        self.emit(Op::Return, Span::default());
+        self.chunk
+    }
+}
+
+pub fn compile(expr: &Expr) -> Chunk {
+    Compiler { chunk: Chunk::default(), names: HashMap::new(), scopes: Vec::new() }.finish(expr)
+}
+
+// The parameters take the first slots, in order.
+fn compile_fn(f: &Function) -> Chunk {
+    let params = f.params.iter().enumerate().map(|(i, p)| Local { name: p.clone(), slot: i as u32, maybe: false }).collect();
+    let chunk = Chunk { slots: f.params.len(), ..Chunk::default() };
+    Compiler { chunk, names: HashMap::new(), scopes: vec![params] }.finish(&f.body)
+}
+
+// A caller waiting for a call to return: where to resume and its locals'
+// base, plus the call's callee name (an index into the caller's names) and
+// span.
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    base: usize,
+    name: u32,
+    call: Span,
+}
+
+// Runs one compiled statement. User functions are compiled on their first
+// call and run on the same value stack, so deep recursion does not grow the
+// Rust stack.
+pub fn run(chunk: Rc<Chunk>, env: &mut Env) -> Result<Value, Error> {
+    let calls = env.calls;
+    let mut frames = Vec::new();
+
+    execute(chunk, &mut frames, env).map_err(|e| {
+        env.calls = calls;
+
+        // Like the tree walker, errors escaping a user function are reported
+        // at the outermost call.
+        match frames.first() {
+            Some(outer) => eval::relocate(e, outer.call, &outer.chunk.names[outer.name as usize]),
+            None => e,
+        }
+    })
+}
+
+fn execute(mut chunk: Rc<Chunk>, frames: &mut Vec<Frame>, env: &mut Env) -> Result<Value, Error> {
+    let mut stack: Vec<Value> = Vec::new();
+    let mut locals: Vec<Option<Value>> = vec![None; chunk.slots];
+    let mut callees: Vec<Callee> = Vec::new();
+    let (mut ip, mut base) = (0, 0);
+
+    loop {
+        let op = chunk.code[ip];
+        let span = chunk.spans[ip];
+        ip += 1;
+
+        match op {
+            Op::Const(i) => stack.push(chunk.consts[i as usize].clone()),
+            Op::BigConst(i) => stack.push(eval::literal(&chunk.consts[i as usize], span, env.mode)?),
+            Op::Bool(b) => stack.push(Value::Bool(b)),
+            Op::Unit => stack.push(Value::Unit),
+            Op::Pop => {
+                stack.pop();
+            }
+
+            Op::Load(i) => stack.push(eval::load(env, &chunk.names[i as usize], span)?),
+            Op::Define(i) => env.define(&chunk.names[i as usize], stack.last().unwrap().clone()),
+            Op::Assign(i) => env.assign(&chunk.names[i as usize], stack.last().unwrap().clone()),
+            Op::LoadLocal(slot) => stack.push(locals[base + slot as usize].clone().expect("let runs before the uses it reaches")),
+            Op::StoreLocal(slot) => locals[base + slot as usize] = stack.last().cloned(),
+            Op::LoadMaybe(slot, i) => match &locals[base + slot as usize] {
+                Some(v) => stack.push(v.clone()),
+                None => stack.push(eval::load(env, &chunk.names[i as usize], span)?),
+            },
+            Op::AssignMaybe(slot, i) => {
+                let name = &chunk.names[i as usize];
+                let local = &mut locals[base + slot as usize];
+                if local.is_none() && env.scopes[0].contains_key(name) {
+                    env.assign(name, stack.last().unwrap().clone());
+                } else {
+                    *local = stack.last().cloned();
+                }
+            }
+            Op::ClearLocals(first, n) => locals[base + first as usize..][..n as usize].fill(None),
+            Op::DefineFn(i) => {
+                let (name, f) = &chunk.functions[i as usize];
+                eval::define_fn(env, name, f.clone(), span)?;
+            }
+
+            Op::Neg => {
+                let v = stack.pop().unwrap();
+                stack.push(v.negate(env.mode).map_err(|e| e.at(span))?);
+            }
+            Op::Not => {
+                let v = stack.pop().unwrap();
+                stack.push(Value::Bool(!eval::expect_bool(v, "operand of '!'", span)?));
+            }
+            Op::Binary(op) => {
+                let r = stack.pop().unwrap();
+                let l = stack.pop().unwrap();
+                stack.push(value::binary(op, &l, &r, env.mode).map_err(|e| e.at(span))?);
+            }
+
+            Op::CheckBool(what) => {
+                let v = stack.pop().unwrap();
+                stack.push(Value::Bool(eval::expect_bool(v, what, span)?));
+            }
+            Op::Jump(target) => ip = target as usize,
+            Op::JumpIfFalse(target, what) => {
+                if !eval::expect_bool(stack.pop().unwrap(), what, span)? {
+                    ip = target as usize;
+                }
+            }
+
+            Op::Callee(name, argc) => {
+                callees.push(eval::resolve_call(env, &chunk.names[name as usize], argc as usize, span)?);
+            }
+            Op::Call(name, argc) => {
+                let args = stack.len() - argc as usize;
+                match callees.pop().unwrap() {
+                    Callee::Builtin(f) => {
+                        let v = eval::call_builtin(&chunk.names[name as usize], f, &stack[args..], span, env.mode)?;
+                        stack.truncate(args);
+                        stack.push(v);
+                    }
+                    Callee::User(f) => {
+                        eval::count_call(env, span)?;
+                        let code = f.code.get_or_init(|| Rc::new(compile_fn(&f))).clone();
+                        let caller_base = std::mem::replace(&mut base, locals.len());
+                        locals.extend(stack.drain(args..).map(Some));
+                        locals.resize(base + code.slots, None);
+                        let caller = std::mem::replace(&mut chunk, code);
+                        frames.push(Frame { chunk: caller, ip, base: caller_base, name, call: span });
+                        ip = 0;
+                    }
+                }
+            }
+
+            Op::Fail => return Err(eval::syntax_error(span)),
+
+            Op::Return => {
+                let Some(caller) = frames.pop() else {
+                    return Ok(stack.pop().unwrap_or(Value::Unit));
+                };
+                env.calls -= 1;
+                locals.truncate(base);
+                (chunk, ip, base) = (caller.chunk, caller.ip, caller.base);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::value::Numeric;
+    use crate::eval::{Backend, Env, execute};
+    use crate::lexer::lex;
+    use crate::parser::Parser;
+
+    fn outputs(src: &str, backend: Backend, configure: fn(&mut Env)) -> Vec<String> {
+        let mut env = Env { backend, ..Env::default() };
+        configure(&mut env);
+        let mut parser = Parser::new(lex(src));
+        let mut out = Vec::new();
+
+        loop {
+            parser.skip_separators();
+            if parser.at_end() {
+                return out;
+            }
+            match parser.parse_statement() {
+                Ok(stmt) => out.push(match execute(&stmt, &mut env) {
+                    Ok(v) => v.to_string(),
+                    Err(e) => format!("{:?}", e),
+                }),
+                Err(e) => out.push(format!("{:?}", e)),
+            }
+        }
+    }
+
+    fn assert_backends_agree(src: &str, configure: fn(&mut Env)) {
+        let tree = outputs(src, Backend::Tree, configure);
+        let vm = outputs(src, Backend::Vm, configure);
+        assert_eq!(tree, vm, "script:\n{}", src);
+    }
+
+    const SCRIPTS: &[&str] = &[
+        "1 + 2 * 3\n(1 + 2) * 3\n-4 / 2\n7 / 2\n2 - -3",
+        "let x = 5\nx = x * 2\nx\ny\nlet y = x + 1; y",
+        "1 < 2\n2 <= 2\n3 > 4\n4 >= 5\n1 == 1.0\ntrue != false\n1 == true",
+        "true && false\nfalse || true\ntrue || 1 / 0 == 0\nfalse && x\n1 && true\ntrue || 1\n!true\n!1",
+        "if 1 < 2 { 10 } else { 20 }\nif false { 1 }\nif false { 1 } else if true { 2 } else { 3 }\nif 1 { 2 }",
+        "let s = 0\nlet i = 0\nwhile i < 100 { s = s + i; i = i + 1 }\ns\nwhile 1 { }",
+        "let x = 1\n{ let x = 2; x = 3; x }\nx\n{ y = 4 }\ny\n{}\n{ let a = 1 }",
+        "fn fact(n) { if n <= 1 { 1 } else { n * fact(n - 1) } }\nfact(10)\nfact(20)\nfact(21)",
+        "fn fib(n) = if n < 2 { n } else { fib(n - 1) + fib(n - 2) }\nfib(15)",
+        "fn f(n) = if n == 0 { 1 / 0 } else { f(n - 1) }\nmax(1, f(3))\nfn r(n) = r(n + 1)\nr(0)",
+        "min(1, 2)\nmax(3, 1, 2)\nabs(true)\npow(2, 10)\nclamp(5, 10, 1)\nnope(1 / 0)\nfn min(a) = a",
+        "fn g(a, b) = a + b\ng(1)\ng(1, 2)\nlet a = 100\nfn h() = a\nh()\nfn k(x) = { let a = x; a }\nk(7)\na",
+        "9223372036854775807 + 1\n99999999999999999999\n1 / 0\n-(0 - 9223372036854775807 - 1)",
+        "fn outer(x) {\n  fn inner(y) = y * 2\n  inner(x) + 1\n}\nouter(4)\ninner(5)",
+        "1 +\n2 )\nlet = 4\n{ 1 +\n}\n5",
+        "let s = \"ab\" + \"c\" # joined\ns + s\n\"a\" < \"b\"\n\"x\" == \"x\"\n\"x\" - \"y\"\n\"a\" + 1\nmax(\"a\", 1)\n-\"a\"",
+        "fn h() { y = 1; g(); y }\nfn g() = { y = 5 }\nh()\ny\nlet y = 0\nh()\ny\nfn k() { let y = 2; { y = 3; let y = 4; y = y + 1 }; y }\nk()",
+        "let i = 0\nwhile i < 3 { t = i * 10; i = i + 1; { u = t; u } }\nt\n{ let a = 1; a }\n{ a = 2; { a = a + 1; b = a }; a + b }\n{ b }",
+        "fn count(n) { let s = 0; while n > 0 { s = s + n; n = n - 1; let s = 0 }; s }\ncount(4)\nfn shadow(x) { { let x = x * 2; x = x + 1; x } + x }\nshadow(5)",
+    ];
+
+    #[test]
+    fn backends_agree_on_scripts() {
+        for src in SCRIPTS {
+            assert_backends_agree(src, |_| {});
+        }
+    }
+
+    #[test]
+    fn backends_agree_in_other_numeric_modes() {
+        for src in SCRIPTS {
+            assert_backends_agree(src, |env| env.mode.bigint = true);
+            assert_backends_agree(src, |env| env.mode.numeric = Numeric::Rational);
+            assert_backends_agree(src, |env| env.mode.numeric = Numeric::Float);
+        }
+    }
+
+    #[test]
+    fn failed_statement_leaves_env_usable() {
+        let src = "let x = 1\n{ let y = 2; 1 / 0 }\nx\ny\nfn f(n) = { let z = n; 1 / 0 }\nf(1)\nz\nx = 5\nx";
+        assert_backends_agree(src, |_| {});
+        assert_eq!(outputs(src, Backend::Vm, |_| {}).last().unwrap(), "5");
+    }
+}
@@ -0,0 +1,103 @@
+// Uses only the public API, the way an embedding crate would.
+use dfa_lexer::{Backend, Code, Expr, Interpreter, Mode, Numeric, Parser, TokenKind, Value, diag, eval, tokenize};
+
+#[test]
+fn tokenize_reports_first_invalid_character() {
+    let tokens = tokenize("let x = 1").unwrap();
+    assert!(matches!(tokens[0].kind, TokenKind::Let));
+    assert_eq!(tokens.len(), 4);
+
+    let err = tokenize("1 + $").unwrap_err();
+    assert_eq!(err.code, Code::InvalidCharacter);
+    assert_eq!((err.span.start, err.span.end), (4, 5));
+}
+
+#[test]
+fn interpreter_keeps_bindings_between_calls() {
+    let mut interp = Interpreter::new();
+    interp.eval("let x = 20\nfn twice(n) = n * 2").unwrap();
+    assert_eq!(interp.eval("twice(x) + 2").unwrap().to_string(), "42");
+    assert_eq!(interp.get("x").unwrap().to_string(), "20");
+    assert!(matches!(interp.eval("let y = 1").unwrap(), Value::Unit));
+
+    interp.reset();
+    assert!(interp.get("x").is_none());
+}
+
+#[test]
+fn errors_implement_std_error() {
+    let mut interp = Interpreter::new().with_backend(Backend::Vm);
+    let err: Box<dyn std::error::Error> = Box::new(interp.eval("1 / 0").unwrap_err());
+    assert_eq!(err.to_string(), "error[E0104]: division by zero");
+}
+
+#[test]
+fn parser_and_eval_work_on_their_own() {
+    let mode = Mode { numeric: Numeric::Rational, ..Mode::default() };
+    let mut interp = Interpreter::new().with_mode(mode);
+
+    let mut parser = Parser::new(tokenize("1 / 3 + 1 / 6").unwrap());
+    let expr = parser.parse_statement().unwrap();
+    assert!(matches!(expr, Expr::Binary { .. }));
+    assert_eq!(eval(&expr, interp.env_mut()).unwrap().to_string(), "1/2");
+}
+
+#[test]
+fn carets_follow_display_width() {
+    let src = "let 名前 = 1\n名前 + é\u{301}";
+    let err = Interpreter::new().eval(src).unwrap_err();
+    let out = diag::render(src, 1, &err);
+    assert_eq!(out.lines().nth(3), Some("2 | 名前 + é\u{301}"));
+    assert_eq!(out.lines().nth(4), Some("  |        ^"));
+}
+
+#[test]
+fn parser_recovers_at_statement_boundaries() {
+    let src = "let a = (1 +)\nfn f(x) {\n  let = x\n  x * )\n  x\n}\nlet b = (2\nb";
+    let (stmts, errors) = Parser::new(tokenize(src).unwrap()).parse_program();
+
+    let starts: Vec<usize> = errors.iter().map(|e| e.span.start).collect();
+    assert_eq!(starts, [src.find(')').unwrap(), src.find("= x").unwrap(), src.rfind(')').unwrap(), src.len() - 1]);
+    assert_eq!(errors[3].code, Code::UnbalancedParen);
+
+    assert!(matches!(stmts[..], [Expr::Error { .. }, Expr::FnDef { .. }, Expr::Error { .. }]), "{:?}", stmts);
+    let Expr::FnDef { body, .. } = &stmts[1] else { unreachable!() };
+    let Expr::Block { stmts: body, .. } = &**body else { panic!("{:?}", body) };
+    assert!(matches!(body[..], [Expr::Error { .. }, Expr::Error { .. }, Expr::Ident { .. }]), "{:?}", body);
+}
+
+#[test]
+fn block_keeps_good_statements_around_broken_ones() {
+    let mut parser = Parser::new(tokenize("{ let = 1; 2\n3 +\n}\n4").unwrap());
+    let (stmts, errors) = parser.parse_program();
+    assert_eq!(errors.len(), 2);
+    let Expr::Block { stmts: inner, .. } = &stmts[0] else { panic!("{:?}", stmts[0]) };
+    assert!(matches!(inner[..], [Expr::Error { .. }, Expr::Number { .. }, Expr::Error { .. }]), "{:?}", inner);
+    assert!(matches!(stmts[1], Expr::Number { .. }));
+
+    // parse_statement reports the first error and moves past the statement.
+    let mut parser = Parser::new(tokenize("(1 +\n2; 3").unwrap());
+    assert!(parser.parse_statement().is_err());
+    parser.skip_separators();
+    assert!(matches!(parser.parse_statement(), Ok(Expr::Number { .. })));
+}
+
+#[test]
+fn huge_powers_overflow_before_they_are_computed() {
+    let start = std::time::Instant::now();
+    let mode = Mode { numeric: Numeric::Rational, ..Mode::default() };
+    for src in ["pow(3, 2000000)", "pow(-2, 4000000000)", "pow(1 / 2, 2000000)", "pow(3, -2000000)"] {
+        assert_eq!(Interpreter::new().with_mode(mode).eval(src).unwrap_err().code, Code::Overflow, "{}", src);
+    }
+    assert!(start.elapsed().as_secs() < 1, "took {:?}", start.elapsed());
+
+    let mut interp = Interpreter::new();
+    assert_eq!(interp.eval("pow(-2, 63)").unwrap().to_string(), i64::MIN.to_string());
+    assert_eq!(interp.eval("pow(-1, 4000000001)").unwrap().to_string(), "-1");
+    for (src, expected) in [("pow(-1, 10000000001)", "-1"), ("pow(-1, 10000000000)", "1"), ("pow(1, 10000000001)", "1"), ("pow(0, 10000000001)", "0")] {
+        for mode in [Mode::default(), mode, Mode { bigint: true, ..Mode::default() }] {
+            assert_eq!(Interpreter::new().with_mode(mode).eval(src).unwrap().to_string(), expected, "{} in {:?}", src, mode);
+        }
+    }
+    assert_eq!(interp.eval("pow(2, 64)").unwrap_err().code, Code::Overflow);
+}
@@ -0,0 +1,14 @@
+// Snapshot checks shared by the integration tests. Run with
+// UPDATE_SNAPSHOTS=1 to rewrite the expected files after an intended change.
+use std::fs;
+use std::path::Path;
+
+pub fn check_snapshot(path: &Path, actual: &str) {
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let name = path.file_name().unwrap().to_string_lossy();
+    assert!(expected == actual, "{} is out of date; rerun with UPDATE_SNAPSHOTS=1\n{}", name, actual);
+}
@@ -0,0 +1,38 @@
+// Pins the Graphviz output for the built-in token set.
+mod common;
+
+use std::path::PathBuf;
+
+use dfa_lexer::dfa::Dfa;
+use dfa_lexer::nfa::Nfa;
+use dfa_lexer::{TOKEN_SPEC, dot, spec};
+
+fn check(name: &str, actual: &str) {
+    common::check_snapshot(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots").join(name), actual);
+}
+
+fn automata() -> (Vec<spec::Rule>, Nfa) {
+    let rules = spec::parse(TOKEN_SPEC).unwrap();
+    let nfa = Nfa::from_rules(&rules);
+    (rules, nfa)
+}
+
+#[test]
+fn nfa_snapshot() {
+    let (rules, nfa) = automata();
+    check("nfa.dot", &dot::nfa(&nfa, &rules));
+}
+
+#[test]
+fn dfa_snapshot() {
+    let (rules, nfa) = automata();
+    check("dfa.dot", &dot::dfa(&Dfa::from_nfa(&nfa), &rules, "dfa"));
+}
+
+#[test]
+fn min_dfa_snapshot() {
+    let (rules, nfa) = automata();
+    let out = dot::dfa(&Dfa::from_nfa(&nfa).minimize(), &rules, "min_dfa");
+    assert!(out.contains("[shape=doublecircle, label=\"") && out.contains("\\nIDENT\"];"));
+    check("min.dot", &out);
+}
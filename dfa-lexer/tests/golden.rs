@@ -0,0 +1,37 @@
+// Pins what the CLI prints for failing scripts. Each tests/golden/NAME.calc
+// runs with --keep-going and its stderr is compared with NAME.stderr.
+mod common;
+
+use std::path::PathBuf;
+use std::process::Command;
+
+fn check(name: &str) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let output = Command::new(env!("CARGO_BIN_EXE_dfa-lexer"))
+        .arg("--keep-going")
+        .arg(dir.join(format!("{}.calc", name)))
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{} should fail", name);
+    common::check_snapshot(&dir.join(format!("{}.stderr", name)), &String::from_utf8(output.stderr).unwrap());
+}
+
+#[test]
+fn syntax_errors() {
+    check("syntax");
+}
+
+#[test]
+fn runtime_errors() {
+    check("runtime");
+}
+
+#[test]
+fn unicode_source() {
+    check("unicode");
+}
+
+#[test]
+fn recursion_limit() {
+    check("recursion");
+}
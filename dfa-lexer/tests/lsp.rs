@@ -0,0 +1,133 @@
+// A scripted client driving `dfa-lexer lsp` over pipes, the way an editor would.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use dfa_lexer::json::{self, Json};
+
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: usize,
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_dfa-lexer"))
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("starting the server");
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Client { child, stdin, stdout, next_id: 1 }
+    }
+
+    fn send(&mut self, method: &str, id: Option<usize>, params: &str) {
+        let id = id.map_or(String::new(), |id| format!("\"id\":{},", id));
+        let body = format!(r#"{{"jsonrpc":"2.0",{}"method":"{}","params":{}}}"#, id, method, params);
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Json {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.stdout.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some(("Content-Length", n)) => length = n.parse().unwrap(),
+                _ if line.trim_end().is_empty() => break,
+                _ => panic!("unexpected header {:?}", line),
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        json::parse(std::str::from_utf8(&body).unwrap()).unwrap()
+    }
+
+    fn request(&mut self, method: &str, params: &str) -> Json {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(method, Some(id), params);
+        let reply = self.receive();
+        assert_eq!(reply.get(&["id"]).and_then(Json::as_usize), Some(id), "{}", reply);
+        reply
+    }
+
+    fn notify(&mut self, method: &str, params: &str) {
+        self.send(method, None, params);
+    }
+
+    // The diagnostics published after opening or changing a document.
+    fn diagnostics(&mut self) -> Vec<Json> {
+        let msg = self.receive();
+        assert_eq!(msg.get(&["method"]).and_then(Json::as_str), Some("textDocument/publishDiagnostics"));
+        msg.get(&["params", "diagnostics"]).and_then(Json::as_array).unwrap().to_vec()
+    }
+}
+
+fn at(line: usize, character: usize) -> String {
+    format!(r#"{{"textDocument":{{"uri":"file:///a.calc"}},"position":{{"line":{},"character":{}}}}}"#, line, character)
+}
+
+fn field<'a>(json: &'a Json, path: &[&str]) -> &'a str {
+    json.get(path).and_then(Json::as_str).unwrap_or_else(|| panic!("no {:?} in {}", path, json))
+}
+
+#[test]
+fn scripted_session() {
+    let mut client = Client::start();
+    let init = client.request("initialize", r#"{"processId":null,"rootUri":null,"capabilities":{}}"#);
+    assert_eq!(init.get(&["result", "capabilities", "hoverProvider"]), Some(&Json::Bool(true)));
+    assert_eq!(init.get(&["result", "capabilities", "definitionProvider"]), Some(&Json::Bool(true)));
+    client.notify("initialized", "{}");
+
+    let text = r#"let width = 6\nlet area = width * 7\nfn half(n) = n / 2\nprint(half(area), 1 / 0)\nlet s = \"😀\" + )"#;
+    client.notify("textDocument/didOpen", &format!(r#"{{"textDocument":{{"uri":"file:///a.calc","languageId":"calc","version":1,"text":"{}"}}}}"#, text));
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+    assert_eq!(field(&diagnostics[0], &["code"]), "E0104");
+    assert_eq!(diagnostics[0].get(&["range", "start"]).unwrap().to_string(), r#"{"line":3,"character":20}"#);
+    assert_eq!(field(&diagnostics[1], &["code"]), "E0004");
+    // The emoji is two UTF-16 code units.
+    assert_eq!(diagnostics[1].get(&["range", "start"]).unwrap().to_string(), r#"{"line":4,"character":15}"#);
+
+    let hover = client.request("textDocument/hover", &at(3, 12));
+    assert_eq!(field(&hover, &["result", "contents", "value"]), "let area = 42");
+    let hover = client.request("textDocument/hover", &at(1, 17));
+    assert_eq!(field(&hover, &["result", "contents", "value"]), "= 42");
+    let hover = client.request("textDocument/hover", &at(3, 2));
+    assert_eq!(field(&hover, &["result", "contents", "value"]), "builtin print, any number of arguments");
+
+    let definition = client.request("textDocument/definition", &at(1, 12));
+    assert_eq!(field(&definition, &["result", "uri"]), "file:///a.calc");
+    assert_eq!(definition.get(&["result", "range"]).unwrap().to_string(), r#"{"start":{"line":0,"character":4},"end":{"line":0,"character":9}}"#);
+    let definition = client.request("textDocument/definition", &at(3, 7));
+    assert_eq!(definition.get(&["result", "range", "start"]).unwrap().to_string(), r#"{"line":2,"character":3}"#);
+
+    let completion = client.request("textDocument/completion", &at(2, 13));
+    let labels: Vec<&str> = completion.get(&["result"]).and_then(Json::as_array).unwrap().iter().map(|i| field(i, &["label"])).collect();
+    for label in ["n", "width", "area", "half", "pow", "while"] {
+        assert!(labels.contains(&label), "{} missing from {:?}", label, labels);
+    }
+
+    client.notify("textDocument/didChange", r#"{"textDocument":{"uri":"file:///a.calc","version":2},"contentChanges":[{"text":"let x = 1"}]}"#);
+    assert!(client.diagnostics().is_empty());
+
+    let unknown = client.request("workspace/symbol", r#"{"query":""}"#);
+    assert_eq!(unknown.get(&["error", "code"]), Some(&Json::Number(-32601.0)));
+
+    let shutdown = client.request("shutdown", "null");
+    assert_eq!(shutdown.get(&["result"]), Some(&Json::Null));
+    client.notify("exit", "null");
+    assert!(client.child.wait().unwrap().success());
+}
+
+#[test]
+fn exit_without_shutdown_fails() {
+    let mut client = Client::start();
+    client.notify("exit", "null");
+    assert_eq!(client.child.wait().unwrap().code(), Some(1));
+}
@@ -0,0 +1,361 @@
+// Randomised checks of the lexer, parser and evaluator against each other
+// and against a small reference evaluator. Everything is seeded, so a
+// failure reproduces; the failing source text is in the assertion message.
+use dfa_lexer::{Backend, Code, Expr, Interpreter, Lexer, Mode, Parser, Value, diff, fmt, optimize, tokenize};
+
+// xorshift, as in the DFA tests: reproducible without extra crates.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// Well-typed expressions: `Int` nodes produce integers, `Bool` nodes bools.
+#[derive(Debug)]
+enum Gen {
+    Num(i64),
+    Var(&'static str, i64),
+    Bool(bool),
+    Unary(&'static str, Box<Gen>),
+    Binary(&'static str, Box<Gen>, Box<Gen>),
+}
+
+const PRELUDE: &str = "let x = 7\nlet y = -3\nlet big = 9223372036854775807";
+const VARS: &[(&str, i64)] = &[("x", 7), ("y", -3), ("big", i64::MAX)];
+
+fn gen_int(rng: &mut Rng, depth: usize) -> Gen {
+    if depth == 0 || rng.below(4) == 0 {
+        return match rng.below(12) {
+            0 | 1 => {
+                let (name, value) = VARS[rng.below(VARS.len())];
+                Gen::Var(name, value)
+            }
+            // Mostly small numbers, sometimes ones near the edge of i64.
+            2 => Gen::Num(i64::MAX - rng.below(3) as i64),
+            _ => Gen::Num(rng.below(20) as i64),
+        };
+    }
+    match rng.below(6) {
+        0 => Gen::Unary("-", Box::new(gen_int(rng, depth - 1))),
+        n => {
+            let op = ["+", "-", "*", "/", "*"][n - 1];
+            Gen::Binary(op, Box::new(gen_int(rng, depth - 1)), Box::new(gen_int(rng, depth - 1)))
+        }
+    }
+}
+
+fn gen_bool(rng: &mut Rng, depth: usize) -> Gen {
+    if depth == 0 || rng.below(5) == 0 {
+        return Gen::Bool(rng.below(2) == 0);
+    }
+    match rng.below(4) {
+        0 => Gen::Unary("!", Box::new(gen_bool(rng, depth - 1))),
+        1 => {
+            let op = ["&&", "||"][rng.below(2)];
+            Gen::Binary(op, Box::new(gen_bool(rng, depth - 1)), Box::new(gen_bool(rng, depth - 1)))
+        }
+        _ => {
+            let op = ["<", "<=", ">", ">=", "==", "!="][rng.below(6)];
+            Gen::Binary(op, Box::new(gen_int(rng, depth - 1)), Box::new(gen_int(rng, depth - 1)))
+        }
+    }
+}
+
+// The precedence levels the language is specified with, loosest first.
+fn level(g: &Gen) -> u8 {
+    match g {
+        Gen::Binary(op, ..) => match *op {
+            "||" => 1,
+            "&&" => 2,
+            "==" | "!=" => 3,
+            "<" | "<=" | ">" | ">=" => 4,
+            "+" | "-" => 5,
+            _ => 6,
+        },
+        Gen::Unary(..) => 7,
+        _ => 8,
+    }
+}
+
+// Source text with only the parentheses precedence and left associativity
+// require, so re-parsing it checks both.
+fn render(g: &Gen) -> String {
+    let wrap = |child: &Gen, parens: bool| if parens { format!("({})", render(child)) } else { render(child) };
+    match g {
+        Gen::Num(n) => n.to_string(),
+        Gen::Var(name, _) => name.to_string(),
+        Gen::Bool(b) => b.to_string(),
+        Gen::Unary(op, operand) => format!("{}{}", op, wrap(operand, level(operand) < 7)),
+        Gen::Binary(op, l, r) => {
+            let p = level(g);
+            format!("{} {} {}", wrap(l, level(l) < p), op, wrap(r, level(r) <= p))
+        }
+    }
+}
+
+fn shape_gen(g: &Gen) -> String {
+    match g {
+        Gen::Num(n) => n.to_string(),
+        Gen::Var(name, _) => name.to_string(),
+        Gen::Bool(b) => b.to_string(),
+        Gen::Unary(op, operand) => format!("({} {})", op, shape_gen(operand)),
+        Gen::Binary(op, l, r) => format!("({} {} {})", op, shape_gen(l), shape_gen(r)),
+    }
+}
+
+fn shape(e: &Expr) -> String {
+    match e {
+        Expr::Number { value, .. } => value.to_string(),
+        Expr::Ident { name, .. } => name.clone(),
+        Expr::Bool(b) => b.to_string(),
+        Expr::Unary { op, operand, .. } => format!("({} {})", op, shape(operand)),
+        Expr::Binary { op, left, right, .. } => format!("({} {} {})", op, shape(left), shape(right)),
+        other => panic!("generator never produces {:?}", other),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Int(i64),
+    Bool(bool),
+    Fail(Code),
+}
+
+// What the language should compute: i64 arithmetic that fails instead of
+// wrapping, `/` truncating toward zero, left before right, and `&&`/`||`
+// skipping their right side (and its errors) when the left decides.
+fn reference(g: &Gen) -> Outcome {
+    let int = |g: &Gen| match reference(g) {
+        Outcome::Int(n) => Ok(n as i128),
+        other => Err(other),
+    };
+    let checked = |v: i128| i64::try_from(v).map_or(Outcome::Fail(Code::Overflow), Outcome::Int);
+
+    match g {
+        Gen::Num(n) | Gen::Var(_, n) => Outcome::Int(*n),
+        Gen::Bool(b) => Outcome::Bool(*b),
+        Gen::Unary("-", operand) => int(operand).map_or_else(|e| e, |n| checked(-n)),
+        Gen::Unary(_, operand) => match reference(operand) {
+            Outcome::Bool(b) => Outcome::Bool(!b),
+            other => other,
+        },
+        Gen::Binary(op @ ("&&" | "||"), l, r) => match reference(l) {
+            Outcome::Bool(b) if b == (*op == "||") => Outcome::Bool(b),
+            Outcome::Bool(_) => reference(r),
+            other => other,
+        },
+        Gen::Binary(op, l, r) => {
+            let (a, b) = match (int(l), int(r)) {
+                (Err(e), _) | (_, Err(e)) => return e,
+                (Ok(a), Ok(b)) => (a, b),
+            };
+            match *op {
+                "+" => checked(a + b),
+                "-" => checked(a - b),
+                "*" => checked(a * b),
+                "/" if b == 0 => Outcome::Fail(Code::DivisionByZero),
+                "/" => checked(a / b),
+                "<" => Outcome::Bool(a < b),
+                "<=" => Outcome::Bool(a <= b),
+                ">" => Outcome::Bool(a > b),
+                ">=" => Outcome::Bool(a >= b),
+                "==" => Outcome::Bool(a == b),
+                _ => Outcome::Bool(a != b),
+            }
+        }
+    }
+}
+
+fn has_vars(g: &Gen) -> bool {
+    match g {
+        Gen::Var(..) => true,
+        Gen::Num(_) | Gen::Bool(_) => false,
+        Gen::Unary(_, operand) => has_vars(operand),
+        Gen::Binary(_, l, r) => has_vars(l) || has_vars(r),
+    }
+}
+
+// What the optimizer folds to a constant.
+fn folds(g: &Gen) -> Option<Outcome> {
+    Some(reference(g)).filter(|o| !has_vars(g) && !matches!(o, Outcome::Fail(_)))
+}
+
+// Whether the optimizer rejects `g` before it runs: it walks operands left
+// to right, skips the right side of `&&`/`||` when the left one folds to the
+// deciding value, and fails on a constant divided by a constant zero.
+fn divides_by_constant_zero(g: &Gen) -> bool {
+    match g {
+        Gen::Num(_) | Gen::Var(..) | Gen::Bool(_) => false,
+        Gen::Unary(_, operand) => divides_by_constant_zero(operand),
+        Gen::Binary(op @ ("&&" | "||"), l, r) => {
+            divides_by_constant_zero(l) || folds(l) != Some(Outcome::Bool(*op == "||")) && divides_by_constant_zero(r)
+        }
+        Gen::Binary(op, l, r) => {
+            divides_by_constant_zero(l)
+                || divides_by_constant_zero(r)
+                || *op == "/" && folds(l).is_some() && folds(r) == Some(Outcome::Int(0))
+        }
+    }
+}
+
+fn outcome(result: Result<Value, dfa_lexer::Error>) -> Outcome {
+    match result {
+        Ok(Value::Int(n)) => Outcome::Int(n),
+        Ok(Value::Bool(b)) => Outcome::Bool(b),
+        Ok(other) => panic!("unexpected value {:?}", other),
+        Err(e) => Outcome::Fail(e.code),
+    }
+}
+
+fn expressions(seed: u64, count: usize) -> Vec<Gen> {
+    let mut rng = Rng(seed);
+    (0..count)
+        .map(|_| if rng.below(3) == 0 { gen_bool(&mut rng, 5) } else { gen_int(&mut rng, 5) })
+        .collect()
+}
+
+#[test]
+fn printed_expressions_parse_back_to_the_same_tree() {
+    for g in expressions(0x5eed, 3000) {
+        let src = render(&g);
+        let mut parser = Parser::new(tokenize(&src).unwrap());
+        let expr = parser.parse_statement().unwrap_or_else(|e| panic!("{}: {}", src, e));
+        assert!(parser.at_end(), "{}", src);
+        assert_eq!(shape(&expr), shape_gen(&g), "{}", src);
+    }
+}
+
+// `render` already prints the canonical form, so the formatter has to
+// reproduce it exactly, including after extra parentheses are added.
+#[test]
+fn formatter_prints_minimal_parentheses() {
+    for g in expressions(0xf0a7, 2000) {
+        let src = render(&g);
+        assert_eq!(fmt::format(&src).unwrap(), format!("{}\n", src));
+        assert_eq!(fmt::format(&format!("(({}))", src)).unwrap(), format!("{}\n", src));
+    }
+}
+
+#[test]
+fn both_backends_match_the_reference_evaluator() {
+    let mut tree = Interpreter::new();
+    let mut vm = Interpreter::new().with_backend(Backend::Vm);
+    tree.eval(PRELUDE).unwrap();
+    vm.eval(PRELUDE).unwrap();
+
+    for g in expressions(0xd1ff, 3000) {
+        let src = render(&g);
+        let expected = if divides_by_constant_zero(&g) { Outcome::Fail(Code::DivisionByZero) } else { reference(&g) };
+        assert_eq!(outcome(tree.eval(&src)), expected, "tree: {}", src);
+        assert_eq!(outcome(vm.eval(&src)), expected, "vm: {}", src);
+    }
+}
+
+#[test]
+fn tokenize_never_panics_on_random_bytes() {
+    let mut rng = Rng(0xf022);
+    for _ in 0..5000 {
+        let bytes: Vec<u8> = (0..rng.below(48)).map(|_| rng.next() as u8).collect();
+
+        // Raw bytes, invalid UTF-8 included, through the streaming lexer.
+        for tok in Lexer::new(&bytes[..]) {
+            if tok.is_err() {
+                break;
+            }
+        }
+
+        let text = String::from_utf8_lossy(&bytes);
+        if let Ok(tokens) = tokenize(&text) {
+            let end = tokens.last().map_or(0, |t| t.span.end);
+            assert!(end <= text.len() && text.is_char_boundary(end), "{:?}", text);
+            Parser::new(tokens).parse_program();
+        }
+    }
+}
+
+// Random token soup gets much further into the parser than random bytes.
+// No `while`, so whatever parses also terminates when run.
+#[test]
+fn parser_and_vm_never_panic_on_token_soup() {
+    const PIECES: &[&str] = &[
+        "1", "2.5", "x", "f", "+", "-", "*", "/", "<", "==", "&&", "!", "(", ")", "{", "}", ",", ";", "\n", "=",
+        "let", "fn", "if", "else", "true", "\"s\"", "# c\n", "/* c */", "$", "\"open",
+    ];
+    let mut rng = Rng(0x50ab);
+    for _ in 0..3000 {
+        let src: Vec<&str> = (0..rng.below(24)).map(|_| PIECES[rng.below(PIECES.len())]).collect();
+        let src = src.join(" ");
+
+        let tokens = Lexer::new(src.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+        let (stmts, errors) = Parser::new(tokens).parse_program();
+        for e in &errors {
+            assert!(e.span.start <= e.span.end && e.span.end <= src.len(), "{:?}: {:?}", src, e);
+        }
+        if errors.is_empty() {
+            let mut vm = Interpreter::new().with_backend(Backend::Vm);
+            for stmt in &stmts {
+                let _ = vm.execute(stmt);
+            }
+        }
+    }
+}
+
+// Random inputs are short; these are not. Each is far past the nesting
+// limit, so the parser reports it instead of overflowing the stack, and the
+// same shapes just inside the limit still parse, format and run.
+#[test]
+fn deep_nesting_never_panics() {
+    let deep = |open: &str, inner: &str, close: &str, n: usize| format!("{}{}{}", open.repeat(n), inner, close.repeat(n));
+    let sources = [
+        deep("(", "1", ")", 10000),
+        deep("-", "1", "", 100000),
+        deep("!", "true", "", 100000),
+        deep("{", "1", "}", 10000),
+        deep("if true { ", "1", " }", 5000),
+        deep("while false { ", "", " }", 5000),
+        deep("f(", "1", ")", 10000),
+        format!("1{}", " + (1".repeat(10000)),
+    ];
+    for src in &sources {
+        let (_, errors) = Parser::new(tokenize(src).unwrap()).parse_program();
+        assert!(errors.iter().any(|e| e.code == Code::NestingLimit), "{:.40}...: {:?}", src, errors);
+        assert!(fmt::format(src).is_err());
+        assert!(Interpreter::new().eval(src).is_err());
+    }
+
+    let shallow = [deep("(", "1", ")", 100), deep("-", "1", "", 100), deep("{", "1", "}", 60), format!("1{}", " + 1".repeat(100))];
+    for src in &shallow {
+        assert!(fmt::format(src).is_ok(), "{:.40}", src);
+        for backend in [Backend::Tree, Backend::Vm] {
+            assert!(Interpreter::new().with_backend(backend).eval(src).is_ok(), "{:.40}", src);
+        }
+    }
+}
+
+// A run of operators at one precedence level is not nesting: it parses, and
+// every pass over the tree walks it, however long it is.
+#[test]
+fn flat_chains_have_no_length_limit() {
+    let src = format!("x{}", " + 1".repeat(10000));
+    let (stmts, errors) = Parser::new(tokenize(&src).unwrap()).parse_program();
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(fmt::format(&src).unwrap().trim_end(), src);
+    let folded = optimize(&stmts[0], Mode::default()).unwrap();
+    assert!(matches!(folded, Expr::Binary { .. }));
+    assert!(diff(&stmts[0], "x", Mode::default()).is_ok());
+    for backend in [Backend::Tree, Backend::Vm] {
+        let mut interp = Interpreter::new().with_backend(backend);
+        assert_eq!(interp.eval(&format!("let x = 1\n{}", src)).unwrap(), Value::Int(10001), "{:?}", backend);
+        let strings = format!("\"a\"{}", " + \"b\"".repeat(200));
+        assert_eq!(interp.eval(&strings).unwrap().to_string(), format!("a{}", "b".repeat(200)));
+    }
+}